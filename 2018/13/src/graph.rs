@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use crate::track::{Heading, Track};
+
+// Where following a segment leads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    // Arrives at the given intersection
+    Intersection(usize),
+    // Comes back round to where the segment started without meeting an
+    // intersection
+    Loop,
+    // The track just stops, carts shouldn't ever get here
+    OffTrack,
+}
+
+// A stretch of track between intersections
+#[derive(Debug, Clone)]
+pub struct Segment {
+    // Every square along the segment as (x, y, heading), where the heading is
+    // the way a cart leaves the square. The first square is the intersection
+    // the segment starts at, or anywhere for a loop. The last square is the
+    // intersection at the end, with the heading carts arrive with.
+    pub squares: Vec<(usize, usize, Heading)>,
+    pub end: End,
+}
+
+// Where a cart is on the graph, as the segment and how far along it
+pub type Position = (usize, usize);
+
+// The track as segments joined up by intersections
+#[derive(Debug, Clone)]
+pub struct Graph {
+    pub segments: Vec<Segment>,
+    pub intersections: Vec<(usize, usize)>,
+    // The segment leaving each intersection going each way, by heading index
+    exits: Vec<[Option<usize>; 4]>,
+    // Which segment each square is on going each way, other than
+    // intersections
+    lookup: HashMap<(usize, usize, Heading), Position>,
+    track: Vec<Track>,
+    width: usize,
+    height: usize,
+}
+
+impl Graph {
+    pub fn new(track: &[Track], width: usize, height: usize) -> Graph {
+        let mut graph = Graph {
+            segments: vec![],
+            intersections: vec![],
+            exits: vec![],
+            lookup: HashMap::new(),
+            track: track.to_vec(),
+            width,
+            height,
+        };
+
+        for (i, &t) in track.iter().enumerate() {
+            if t == Track::Intersection {
+                graph.intersections.push((i % width, i / width));
+            }
+        }
+        for node in 0..graph.intersections.len() {
+            let (x, y) = graph.intersections[node];
+            let mut exits = [None; 4];
+            for &heading in &Heading::ALL {
+                // Only follow the ways which actually have track
+                if let Some((nx, ny)) = graph.step(x, y, heading) {
+                    if graph.get(nx, ny).turn(heading, || heading).is_some() {
+                        exits[heading.index()] = Some(graph.trace(x, y, heading));
+                    }
+                }
+            }
+            graph.exits.push(exits);
+        }
+        graph
+    }
+
+    fn get(&self, x: usize, y: usize) -> Track {
+        self.track[x + (self.width * y)]
+    }
+
+    // The square next to (x, y) going the given way, if it's on the map
+    fn step(&self, x: usize, y: usize, heading: Heading) -> Option<(usize, usize)> {
+        let (dx, dy) = heading.offset();
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if nx < 0 || ny < 0 || nx as usize >= self.width || ny as usize >= self.height {
+            None
+        }
+        else {
+            Some((nx as usize, ny as usize))
+        }
+    }
+
+    fn intersection_at(&self, x: usize, y: usize) -> Option<usize> {
+        self.intersections.iter().position(|&i| i == (x, y))
+    }
+
+    // Follow the track from (x, y) until the next intersection, adding the
+    // segment to the graph and returning its index
+    fn trace(&mut self, x: usize, y: usize, heading: Heading) -> usize {
+        let start = (x, y, heading);
+        let mut squares = vec![start];
+        let (mut x, mut y, mut heading) = start;
+        let end = loop {
+            let (nx, ny) = match self.step(x, y, heading) {
+                Some(next) => next,
+                None => break End::OffTrack,
+            };
+            if let Some(node) = self.intersection_at(nx, ny) {
+                squares.push((nx, ny, heading));
+                break End::Intersection(node);
+            }
+            heading = match self.get(nx, ny).turn(heading, || heading) {
+                Some(h) => h,
+                None => break End::OffTrack,
+            };
+            x = nx;
+            y = ny;
+            if (x, y, heading) == start {
+                break End::Loop;
+            }
+            squares.push((x, y, heading));
+        };
+
+        let index = self.segments.len();
+        let last = match end {
+            End::Intersection(_) => squares.len() - 1,
+            _ => squares.len(),
+        };
+        let first = if end == End::Loop { 0 } else { 1 };
+        for (i, &square) in squares.iter().enumerate().take(last).skip(first) {
+            self.lookup.insert(square, (index, i));
+        }
+        self.segments.push(Segment { squares, end });
+        index
+    }
+
+    // Where a cart at (x, y) going the given way is on the graph. A cart on an
+    // intersection is taken to have already turned. Track not joined up to any
+    // intersections gets its own loop the first time a cart is found on it, and
+    // a cart heading away from a dead end gets the stretch up to the next
+    // intersection, as that's only traced from the other way. None if there's
+    // no track going that way.
+    pub fn locate(&mut self, x: usize, y: usize, heading: Heading) -> Option<Position> {
+        if let Some(node) = self.intersection_at(x, y) {
+            return self.exits[node][heading.index()].map(|s| (s, 0));
+        }
+        if let Some(&position) = self.lookup.get(&(x, y, heading)) {
+            return Some(position);
+        }
+        Some((self.trace(x, y, heading), 0))
+    }
+
+    // Whether a cart at the given position could ever come off the track,
+    // whichever way it turns at the intersections it reaches
+    pub fn can_come_off(&self, (segment, _): Position) -> bool {
+        let mut seen = vec![false; self.segments.len()];
+        let mut to_visit = vec![segment];
+        while let Some(segment) = to_visit.pop() {
+            if seen[segment] {
+                continue;
+            }
+            seen[segment] = true;
+            let s = &self.segments[segment];
+            match s.end {
+                End::OffTrack => return true,
+                End::Loop => {}
+                End::Intersection(node) => {
+                    let (_, _, arriving) = s.squares[s.squares.len() - 1];
+                    for &heading in &[arriving.left(), arriving, arriving.right()] {
+                        match self.exits[node][heading.index()] {
+                            Some(exit) => to_visit.push(exit),
+                            None => return true,
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // The square at a position on the graph, with the cart's heading
+    pub fn square(&self, (segment, i): Position) -> (usize, usize, Heading) {
+        self.segments[segment].squares[i]
+    }
+
+    // Move a cart one square along the graph. Reaching an intersection it
+    // turns the way `choose` says given the heading it arrived with. None if
+    // the cart comes off the track.
+    pub fn advance(&self, (segment, i): Position, choose: impl FnOnce(Heading) -> Heading)
+        -> Option<Position> {
+        let s = &self.segments[segment];
+        if i + 1 == s.squares.len() {
+            return match s.end {
+                End::Loop => Some((segment, 0)),
+                _ => None,
+            };
+        }
+        match s.end {
+            End::Intersection(node) if i + 2 == s.squares.len() => {
+                let heading = choose(s.squares[i + 1].2);
+                self.exits[node][heading.index()].map(|e| (e, 0))
+            }
+            _ => Some((segment, i + 1)),
+        }
+    }
+}
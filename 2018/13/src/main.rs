@@ -0,0 +1,283 @@
+use std::env;
+use std::fs;
+
+mod graph;
+use crate::graph::{Graph, Position};
+
+mod policy;
+use crate::policy::{Cycle, Directions, TurnPolicy};
+
+mod track;
+use crate::track::{Heading, Track};
+
+use grid::{Problem, Problems};
+
+type ErrorHolder = Box<dyn std::error::Error>;
+
+#[derive(Debug, Clone)]
+struct Cart {
+    // Carts are numbered in reading order of where they start
+    id: usize,
+    x: usize,
+    y: usize,
+    heading: Heading,
+    position: Position,
+    policy: Box<dyn TurnPolicy>,
+}
+
+impl Cart {
+    // Move one square along the track, turning at intersections however the
+    // cart's policy says
+    fn advance(&mut self, graph: &Graph) -> Result<(), String> {
+        let policy = &mut self.policy;
+        self.position = graph.advance(self.position, |h| policy.next_turn(h).apply(h))
+            .ok_or_else(|| format!("Cart {} came off the track at {},{}",
+                                   self.id, self.x, self.y))?;
+        let (x, y, heading) = graph.square(self.position);
+        self.x = x;
+        self.y = y;
+        self.heading = heading;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Map {
+    map_vec: Vec<Track>,
+    width: usize,
+    height: usize,
+}
+
+// Two carts crashing into each other
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Crash {
+    // Ticks count from 1
+    tick: usize,
+    x: usize,
+    y: usize,
+    // The cart that moved and the cart it hit
+    carts: (usize, usize),
+}
+
+// How things turned out once the carts stopped crashing
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Outcome {
+    // Every crash in the order they happened
+    crashes: Vec<Crash>,
+    ending: Ending,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Ending {
+    // Where the last cart was at the end of the tick it became the last
+    LastCart(usize, usize),
+    // The last carts all crashed at once
+    NoCarts,
+    // The carts ended up just as they were at an earlier tick, so they'll
+    // keep going round without ever crashing again
+    Repeating { tick: usize, carts: usize },
+}
+
+#[derive(Debug, Clone)]
+struct Tracks {
+    map: Map,
+    graph: Graph,
+    carts: Vec<Cart>,
+    // The number of ticks so far
+    ticks: usize,
+}
+
+impl Tracks {
+    // Put carts on the track, each with the turn policy `policy` gives for its
+    // id. Carts are given as (x, y, heading).
+    fn new<F>(map: Map, carts: &[(usize, usize, Heading)], mut policy: F)
+        -> Result<Tracks, Problems> where F: FnMut(usize) -> Box<dyn TurnPolicy> {
+        let mut graph = Graph::new(&map.map_vec, map.width, map.height);
+        let mut placed = vec![];
+        let mut problems = vec![];
+        for (id, &(x, y, heading)) in carts.iter().enumerate() {
+            match graph.locate(x, y, heading) {
+                Some(position) if !graph.can_come_off(position) =>
+                    placed.push(Cart { id, x, y, heading, position, policy: policy(id) }),
+                _ => problems.push(Problem::at(x, y, format!(
+                    "Cart {} could come off the end of the track", id))),
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(Tracks { map, graph, carts: placed, ticks: 0 })
+        }
+        else {
+            Err(Problems(problems))
+        }
+    }
+
+    // Sort the carts vector so that it's in the order the carts move in
+    fn sort_carts(&mut self) {
+        // Sort by row, with column only mattering for ties
+        self.carts.sort_by_key(|c| c.x + (c.y * 1000000));
+    }
+
+    // Move every cart once, in order. Carts which crash are removed straight
+    // away, so a cart which gets crashed into before its turn doesn't move.
+    // Carts are checked when they're put on the track, so none should come
+    // off it, but that's an error rather than a panic if it happens.
+    fn tick(&mut self) -> Result<Vec<Crash>, String> {
+        self.ticks += 1;
+        self.sort_carts();
+        let mut crashes = vec![];
+        let mut crashed = vec![false; self.carts.len()];
+
+        for i in 0..self.carts.len() {
+            if crashed[i] {
+                continue;
+            }
+
+            self.carts[i].advance(&self.graph)?;
+            let (x, y) = (self.carts[i].x, self.carts[i].y);
+
+            // Check for collisions
+            let other = (0..self.carts.len()).find(|&j| {
+                j != i && !crashed[j] && self.carts[j].x == x && self.carts[j].y == y
+            });
+            if let Some(j) = other {
+                crashed[i] = true;
+                crashed[j] = true;
+                crashes.push(Crash { tick: self.ticks, x, y,
+                                     carts: (self.carts[i].id, self.carts[j].id) });
+            }
+        }
+
+        let mut i = 0;
+        self.carts.retain(|_| {
+            i += 1;
+            !crashed[i - 1]
+        });
+        Ok(crashes)
+    }
+
+    // Everything which decides what the carts do from now on. Cart ids are
+    // left out since they don't change where the carts go.
+    fn state(&self) -> Vec<(Position, Vec<Directions>)> {
+        let mut state: Vec<_> = self.carts.iter().map(|c| (c.position, c.policy.state()))
+            .collect();
+        state.sort();
+        state
+    }
+
+    // Keep going, removing the carts as they crash, until there's at most one
+    // cart left or the carts start going round in circles. Circles are spotted
+    // with Brent's algorithm, comparing against a saved state which moves on
+    // every power of two ticks, so we don't have to remember every state.
+    fn run_to_last_cart(&mut self) -> Result<Outcome, String> {
+        let mut crashes = vec![];
+        let mut saved = (self.ticks, self.state());
+        let mut power = 1;
+        let ending = loop {
+            match self.carts.len() {
+                0 => break Ending::NoCarts,
+                1 => break Ending::LastCart(self.carts[0].x, self.carts[0].y),
+                _ => {}
+            }
+
+            crashes.extend(self.tick()?);
+            let state = self.state();
+            if state == saved.1 {
+                break Ending::Repeating { tick: saved.0, carts: self.carts.len() };
+            }
+            if self.ticks - saved.0 == power {
+                saved = (self.ticks, state);
+                power *= 2;
+            }
+        };
+        Ok(Outcome { crashes, ending })
+    }
+}
+
+impl std::fmt::Display for Tracks {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut printing_map: Vec<_> = self.map.map_vec.iter().map(|t| t.glyph()).collect();
+        for c in &self.carts {
+            printing_map[c.x + (self.map.width * c.y)] = c.heading.glyph();
+        }
+        let mut map_string = String::new();
+        for row_index in 0..self.map.height {
+            let mut row: String = printing_map.iter()
+                                    .skip(row_index * self.map.width)
+                                    .take(self.map.width).collect();
+            row.push('\n');
+            map_string.push_str(&row);
+        }
+        write!(f, "{}", map_string)
+    }
+}
+
+fn main() -> Result<(), ErrorHolder> {
+    let input = fs::read_to_string("input.txt")?;
+
+    let grid = match grid::load(&input, |c| " -|/\\+<>^v".contains(c)) {
+        Ok(grid) => grid,
+        Err(problems) => {
+            eprintln!("{}", problems);
+            std::process::exit(1);
+        }
+    };
+    let layout = match track::parse(&grid) {
+        Ok(layout) => layout,
+        Err(problems) => {
+            eprintln!("{}", problems);
+            std::process::exit(1);
+        }
+    };
+    let map = Map { map_vec: layout.track, width: grid.width, height: grid.height };
+
+    // Pass `turns` followed by patterns of L, S and R to change which way the
+    // carts turn at intersections, e.g. `turns SR L` has the even numbered
+    // carts alternate between going straight and turning right while the odd
+    // numbered ones always turn left
+    let args: Vec<_> = env::args().collect();
+    let policies = if args.get(1).map(String::as_str) == Some("turns") {
+        args[2..].iter()
+            .map(|p| Cycle::parse(p).ok_or_else(|| format!("Bad turn pattern '{}'", p)))
+            .collect::<Result<Vec<_>, _>>()?
+    }
+    else {
+        vec![Cycle::puzzle()]
+    };
+    if policies.is_empty() {
+        return Err("No turn patterns given".into());
+    }
+
+    let tracks = match Tracks::new(map, &layout.carts,
+                                   |id| Box::new(policies[id % policies.len()].clone())) {
+        Ok(tracks) => tracks,
+        Err(problems) => {
+            eprintln!("{}", problems);
+            std::process::exit(1);
+        }
+    };
+    println!("{}", tracks);
+
+    // Keep going until one cart is left, Part 1 is where the first crash is
+    let mut final_tracks = tracks.clone();
+    let outcome = final_tracks.run_to_last_cart()?;
+    for crash in &outcome.crashes {
+        println!("Tick {}: carts {} and {} crashed at {},{}",
+                 crash.tick, crash.carts.0, crash.carts.1, crash.x, crash.y);
+    }
+
+    let part1_answer = outcome.crashes.first().map(|c| (c.x, c.y));
+    println!("First collision at {:?}!", part1_answer);
+
+    match outcome.ending {
+        Ending::LastCart(x, y) =>
+            println!("The last remaining cart is located at {:?}!", Some((x, y))),
+        Ending::NoCarts => println!("Every cart crashed!"),
+        Ending::Repeating { tick, carts } =>
+            println!("The {} carts left went back to how they were after tick {}, \
+                      so no further crashes will ever happen!", carts, tick),
+    }
+
+    Ok(())
+}
+
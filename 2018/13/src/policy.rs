@@ -0,0 +1,84 @@
+use crate::track::Heading;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Directions {
+    Straight,
+    Left,
+    Right,
+}
+
+impl Directions {
+    pub fn apply(self, heading: Heading) -> Heading {
+        match self {
+            Directions::Straight => heading,
+            Directions::Left => heading.left(),
+            Directions::Right => heading.right(),
+        }
+    }
+}
+
+// How a cart decides which way to go at intersections
+pub trait TurnPolicy: std::fmt::Debug {
+    fn next_turn(&mut self, heading: Heading) -> Directions;
+
+    // Everything about the policy which affects its future choices, so we can
+    // spot when the carts have got back to where they were before. Two carts
+    // with the same state will make the same choices from now on.
+    fn state(&self) -> Vec<Directions>;
+
+    fn clone_box(&self) -> Box<dyn TurnPolicy>;
+}
+
+impl Clone for Box<dyn TurnPolicy> {
+    fn clone(&self) -> Box<dyn TurnPolicy> {
+        self.clone_box()
+    }
+}
+
+// Go through a list of turns in order, starting again at the end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle {
+    turns: Vec<Directions>,
+    next: usize,
+}
+
+impl Cycle {
+    pub fn new(turns: Vec<Directions>) -> Cycle {
+        assert!(!turns.is_empty(), "A cycle needs at least one turn");
+        Cycle { turns, next: 0 }
+    }
+
+    // Carts in the puzzle turn left, then go straight, then turn right
+    pub fn puzzle() -> Cycle {
+        Cycle::new(vec![Directions::Left, Directions::Straight, Directions::Right])
+    }
+
+    // Read turns from letters, e.g. "LSR" for the puzzle's carts
+    pub fn parse(s: &str) -> Option<Cycle> {
+        let turns = s.chars().map(|c| match c {
+            'L' => Some(Directions::Left),
+            'S' => Some(Directions::Straight),
+            'R' => Some(Directions::Right),
+            _ => None,
+        }).collect::<Option<Vec<_>>>()?;
+        if turns.is_empty() { None } else { Some(Cycle::new(turns)) }
+    }
+}
+
+impl TurnPolicy for Cycle {
+    fn next_turn(&mut self, _heading: Heading) -> Directions {
+        let turn = self.turns[self.next];
+        self.next = (self.next + 1) % self.turns.len();
+        turn
+    }
+
+    // The turns still to come, starting from the next one. Where the cart is
+    // in its cycle isn't enough on its own, as carts can have different cycles.
+    fn state(&self) -> Vec<Directions> {
+        self.turns[self.next..].iter().chain(&self.turns[..self.next]).cloned().collect()
+    }
+
+    fn clone_box(&self) -> Box<dyn TurnPolicy> {
+        Box::new(self.clone())
+    }
+}
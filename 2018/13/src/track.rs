@@ -0,0 +1,242 @@
+use grid::{Grid, Problem, Problems};
+
+// Which way a cart is going
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Heading {
+    North,
+    East,
+    South,
+    West,
+}
+
+use Heading::*;
+
+impl Heading {
+    pub const ALL: [Heading; 4] = [North, East, South, West];
+
+    pub fn from_glyph(c: char) -> Option<Heading> {
+        match c {
+            '^' => Some(North),
+            '>' => Some(East),
+            'v' => Some(South),
+            '<' => Some(West),
+            _ => None,
+        }
+    }
+
+    pub fn glyph(self) -> char {
+        match self {
+            North => '^',
+            East => '>',
+            South => 'v',
+            West => '<',
+        }
+    }
+
+    // The position of the heading in ALL
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn left(self) -> Heading {
+        match self {
+            North => West,
+            East => North,
+            South => East,
+            West => South,
+        }
+    }
+
+    pub fn right(self) -> Heading {
+        self.left().reverse()
+    }
+
+    pub fn reverse(self) -> Heading {
+        self.left().left()
+    }
+
+    // How x and y change moving one square this way, y goes down the map
+    pub fn offset(self) -> (isize, isize) {
+        match self {
+            North => (0, -1),
+            East => (1, 0),
+            South => (0, 1),
+            West => (-1, 0),
+        }
+    }
+}
+
+// A single square of the map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Track {
+    Empty,
+    Horizontal,
+    Vertical,
+    // A `/` curve
+    Slash,
+    // A `\` curve
+    Backslash,
+    Intersection,
+}
+
+impl Track {
+    pub fn from_glyph(c: char) -> Option<Track> {
+        match c {
+            ' ' => Some(Track::Empty),
+            '-' => Some(Track::Horizontal),
+            '|' => Some(Track::Vertical),
+            '/' => Some(Track::Slash),
+            '\\' => Some(Track::Backslash),
+            '+' => Some(Track::Intersection),
+            _ => None,
+        }
+    }
+
+    pub fn glyph(self) -> char {
+        match self {
+            Track::Empty => ' ',
+            Track::Horizontal => '-',
+            Track::Vertical => '|',
+            Track::Slash => '/',
+            Track::Backslash => '\\',
+            Track::Intersection => '+',
+        }
+    }
+
+    // Which way a cart is going once it's moved on to this piece of track,
+    // or None if it's come off the track. Intersections go the way the cart
+    // chooses.
+    pub fn turn(self, heading: Heading, at_intersection: impl FnOnce() -> Heading)
+        -> Option<Heading> {
+        match (self, heading) {
+            (Track::Horizontal, East) | (Track::Horizontal, West) |
+            (Track::Vertical, North) | (Track::Vertical, South) => Some(heading),
+            (Track::Slash, East) | (Track::Slash, West) => Some(heading.left()),
+            (Track::Slash, _) => Some(heading.right()),
+            (Track::Backslash, East) | (Track::Backslash, West) => Some(heading.right()),
+            (Track::Backslash, _) => Some(heading.left()),
+            (Track::Intersection, _) => Some(at_intersection()),
+            _ => None,
+        }
+    }
+}
+
+fn is_horizontal(c: char) -> bool {
+    "-+<>".contains(c)
+}
+
+fn is_vertical(c: char) -> bool {
+    "|+^v".contains(c)
+}
+
+// Whether the character at (x, y) of the raw map has track leaving it going
+// the given way. A curve could be either of two corners, so we go by whether
+// there's straight track beside it. Carts are taken to be on straight track.
+fn connects(grid: &Grid, x: isize, y: isize, heading: Heading) -> bool {
+    if x < 0 || y < 0 || x as usize >= grid.width || y as usize >= grid.height {
+        return false;
+    }
+    let (x, y) = (x as usize, y as usize);
+    let beside = |h: Heading| {
+        let (dx, dy) = h.offset();
+        let (bx, by) = (x as isize + dx, y as isize + dy);
+        if bx < 0 || by < 0 || bx as usize >= grid.width || by as usize >= grid.height {
+            ' '
+        }
+        else {
+            grid.get(bx as usize, by as usize)
+        }
+    };
+
+    let c = grid.get(x, y);
+    if let Some(h) = Heading::from_glyph(c) {
+        return heading == h || heading == h.reverse();
+    }
+    let ways = match Track::from_glyph(c) {
+        Some(Track::Horizontal) => vec![East, West],
+        Some(Track::Vertical) => vec![North, South],
+        Some(Track::Intersection) => Heading::ALL.to_vec(),
+        Some(Track::Slash) if is_horizontal(beside(East)) || is_vertical(beside(South)) =>
+            vec![East, South],
+        Some(Track::Slash) => vec![West, North],
+        Some(Track::Backslash) if is_horizontal(beside(East)) || is_vertical(beside(North)) =>
+            vec![East, North],
+        Some(Track::Backslash) => vec![West, South],
+        _ => vec![],
+    };
+    ways.contains(&heading)
+}
+
+// Work out the piece of track under a cart from the track around it. The cart
+// has to be able to keep going the way it's heading, and it's taken to be on
+// straight track unless it can only have come from the side.
+fn track_under_cart(grid: &Grid, x: usize, y: usize, heading: Heading) -> Option<Track> {
+    let linked = |h: Heading| {
+        let (dx, dy) = h.offset();
+        connects(grid, x as isize + dx, y as isize + dy, h.reverse())
+    };
+    if !linked(heading) {
+        return None;
+    }
+
+    let behind = linked(heading.reverse());
+    let sides = (linked(heading.left()), linked(heading.right()));
+    let straight = match heading {
+        North | South => Track::Vertical,
+        East | West => Track::Horizontal,
+    };
+    match (behind, sides) {
+        (true, (true, true)) => Some(Track::Intersection),
+        (true, _) => Some(straight),
+        // Came round a curve from the side
+        (false, (true, false)) | (false, (false, true)) => {
+            let from = if sides.0 { heading.left() } else { heading.right() };
+            let ways = [heading, from];
+            if ways.contains(&East) == ways.contains(&South) {
+                Some(Track::Slash)
+            }
+            else {
+                Some(Track::Backslash)
+            }
+        }
+        _ => None,
+    }
+}
+
+// The track with the carts taken off it
+pub struct Layout {
+    // The track in reading order
+    pub track: Vec<Track>,
+    // Where each cart starts, as (x, y, heading) in reading order
+    pub carts: Vec<(usize, usize, Heading)>,
+}
+
+// Turn the raw map into track and the carts on it
+pub fn parse(grid: &Grid) -> Result<Layout, Problems> {
+    let mut track = vec![];
+    let mut carts = vec![];
+    let mut problems = vec![];
+    for (x, y, c) in grid.iter() {
+        if let Some(heading) = Heading::from_glyph(c) {
+            carts.push((x, y, heading));
+            track.push(track_under_cart(grid, x, y, heading).unwrap_or_else(|| {
+                problems.push(Problem::at(x, y, format!(
+                    "Can't work out what track the cart '{}' is on", c)));
+                Track::Empty
+            }));
+        }
+        else {
+            track.push(Track::from_glyph(c).unwrap_or_else(|| {
+                problems.push(Problem::at(x, y, format!("Unknown character '{}'", c)));
+                Track::Empty
+            }));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(Layout { track, carts })
+    }
+    else {
+        Err(Problems(problems))
+    }
+}
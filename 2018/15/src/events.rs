@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use failure::{Error, format_err};
+
+use crate::{Map, SquareType::*};
+
+// Something which happened during a battle. Units are identified by the id
+// they were given when the map was read in, which is their reading order
+// position at the start of the battle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Move { round: usize, unit: usize, from: (usize, usize), to: (usize, usize) },
+    // hp is what the target has left after the attack
+    Attack { round: usize, unit: usize, target: usize, damage: i32, hp: i32 },
+    // hp is what the target has after being healed
+    Heal { round: usize, unit: usize, target: usize, amount: i32, hp: i32 },
+    Death { round: usize, unit: usize, at: (usize, usize), killer: usize },
+    // A unit found no targets left, ending the battle part way through a round
+    End { round: usize, unit: usize },
+}
+use self::Event::*;
+
+impl Event {
+    pub fn round(&self) -> usize {
+        match *self {
+            Move { round, .. } | Attack { round, .. } | Heal { round, .. } |
+            Death { round, .. } | End { round, .. } => round,
+        }
+    }
+}
+
+// Written out as a single line of JSON
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Move { round, unit, from, to } =>
+                write!(f, "{{\"round\":{},\"event\":\"move\",\"unit\":{},\
+                           \"from\":[{},{}],\"to\":[{},{}]}}",
+                       round, unit, from.0, from.1, to.0, to.1),
+            Attack { round, unit, target, damage, hp } =>
+                write!(f, "{{\"round\":{},\"event\":\"attack\",\"unit\":{},\
+                           \"target\":{},\"damage\":{},\"hp\":{}}}",
+                       round, unit, target, damage, hp),
+            Heal { round, unit, target, amount, hp } =>
+                write!(f, "{{\"round\":{},\"event\":\"heal\",\"unit\":{},\
+                           \"target\":{},\"amount\":{},\"hp\":{}}}",
+                       round, unit, target, amount, hp),
+            Death { round, unit, at, killer } =>
+                write!(f, "{{\"round\":{},\"event\":\"death\",\"unit\":{},\
+                           \"at\":[{},{}],\"killer\":{}}}",
+                       round, unit, at.0, at.1, killer),
+            End { round, unit } =>
+                write!(f, "{{\"round\":{},\"event\":\"end\",\"unit\":{}}}", round, unit),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Number(i64),
+    Text(String),
+    Pair(i64, i64),
+}
+
+// Split a flat JSON object into its fields. Only handles what Event writes
+// out, i.e. integers, strings without escapes and pairs of integers.
+fn parse_fields(line: &str) -> Option<HashMap<String, Value>> {
+    let inner = line.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let mut fields = HashMap::new();
+    let mut rest = inner.trim();
+    while !rest.is_empty() {
+        let after_quote = rest.strip_prefix('"')?;
+        let end = after_quote.find('"')?;
+        let key = after_quote[..end].to_string();
+        rest = after_quote[end + 1..].trim_start().strip_prefix(':')?.trim_start();
+
+        let (value, remaining) = if let Some(text) = rest.strip_prefix('"') {
+            let end = text.find('"')?;
+            (Value::Text(text[..end].to_string()), &text[end + 1..])
+        }
+        else if let Some(pair) = rest.strip_prefix('[') {
+            let end = pair.find(']')?;
+            let mut numbers = pair[..end].split(',').map(|n| n.trim().parse().ok());
+            let value = Value::Pair(numbers.next()??, numbers.next()??);
+            if numbers.next().is_some() {
+                return None;
+            }
+            (value, &pair[end + 1..])
+        }
+        else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            (Value::Number(rest[..end].trim().parse().ok()?), &rest[end..])
+        };
+
+        fields.insert(key, value);
+        rest = remaining.trim_start();
+        if let Some(next) = rest.strip_prefix(',') {
+            rest = next.trim_start();
+            if rest.is_empty() {
+                return None;
+            }
+        }
+        else if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(fields)
+}
+
+impl std::str::FromStr for Event {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Event, Error> {
+        let fields = parse_fields(line)
+            .ok_or_else(|| format_err!("Failed to parse '{}' as a JSON object", line))?;
+        let number = |key: &str| match fields.get(key) {
+            Some(&Value::Number(n)) => Ok(n),
+            _ => Err(format_err!("Expected a number for '{}' in '{}'", key, line)),
+        };
+        let pair = |key: &str| match fields.get(key) {
+            Some(&Value::Pair(x, y)) => match (usize::try_from(x), usize::try_from(y)) {
+                (Ok(x), Ok(y)) => Ok((x, y)),
+                _ => Err(format_err!("[{}, {}] for '{}' isn't on any map in '{}'",
+                                     x, y, key, line)),
+            },
+            _ => Err(format_err!("Expected a pair of numbers for '{}' in '{}'", key, line)),
+        };
+
+        let round = number("round")? as usize;
+        let unit = number("unit")? as usize;
+        match fields.get("event") {
+            Some(Value::Text(t)) if t == "move" =>
+                Ok(Move { round, unit, from: pair("from")?, to: pair("to")? }),
+            Some(Value::Text(t)) if t == "attack" =>
+                Ok(Attack { round, unit, target: number("target")? as usize,
+                            damage: number("damage")? as i32, hp: number("hp")? as i32 }),
+            Some(Value::Text(t)) if t == "heal" =>
+                Ok(Heal { round, unit, target: number("target")? as usize,
+                          amount: number("amount")? as i32, hp: number("hp")? as i32 }),
+            Some(Value::Text(t)) if t == "death" =>
+                Ok(Death { round, unit, at: pair("at")?, killer: number("killer")? as usize }),
+            Some(Value::Text(t)) if t == "end" => Ok(End { round, unit }),
+            _ => Err(format_err!("Unknown event in '{}'", line)),
+        }
+    }
+}
+
+// Rebuild the map as it was after the given number of complete rounds by
+// replaying the events from the start of the battle. Checks that every event
+// makes sense on the map it's applied to.
+pub fn replay(start: &Map, events: &[Event], rounds: usize) -> Result<Map, Error> {
+    let mut map = start.clone();
+    let (width, height) = (map.width, map.height);
+    let on_map = |(x, y): (usize, usize)| if x < width && y < height {
+        Ok(())
+    }
+    else {
+        Err(format_err!("{:?} is outside the {}x{} map", (x, y), width, height))
+    };
+    for event in events.iter().take_while(|e| e.round() <= rounds) {
+        match *event {
+            Move { unit, from, to, .. } => {
+                on_map(from)?;
+                on_map(to)?;
+                let square = map.get(from.0, from.1);
+                if square.data.as_ref().map(|d| d.id) != Some(unit) {
+                    return Err(format_err!("Unit {} isn't at {:?} to move", unit, from));
+                }
+                if map.get(to.0, to.1).t != Open {
+                    return Err(format_err!("Unit {} can't move to {:?}", unit, to));
+                }
+                let destination = map.get_mut_ref(to.0, to.1);
+                destination.t = square.t;
+                destination.data = square.data;
+                let origin = map.get_mut_ref(from.0, from.1);
+                origin.t = Open;
+                origin.data = None;
+            }
+            Attack { target, damage: change, hp, .. } |
+            Heal { target, amount: change, hp, .. } => {
+                let square = map.find_unit(target)
+                    .ok_or_else(|| format_err!("Unit {} isn't on the map", target))?;
+                let data = map.get_mut_ref(square.x, square.y).data.as_mut().unwrap();
+                data.hp += if let Attack { .. } = event { -change } else { change };
+                if data.hp != hp {
+                    return Err(format_err!("Unit {} should have {} HP but has {}",
+                                           target, hp, data.hp));
+                }
+            }
+            Death { unit, at, .. } => {
+                on_map(at)?;
+                let square = map.get_mut_ref(at.0, at.1);
+                if square.data.as_ref().map(|d| d.id) != Some(unit) {
+                    return Err(format_err!("Unit {} isn't at {:?} to die", unit, at));
+                }
+                square.t = Open;
+                square.data = None;
+            }
+            // The battle ended, so the round it ended in was never completed
+            End { round, .. } => {
+                map.rounds = round - 1;
+                return Ok(map);
+            }
+        }
+    }
+    map.rounds = rounds;
+    Ok(map)
+}
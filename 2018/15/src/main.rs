@@ -1,24 +1,49 @@
+use std::collections::HashSet;
+use std::env;
 use std::fs;
+use std::path::Path;
+use std::process;
+use std::thread;
 use std::cmp::Ordering;
+use std::time::{Duration, Instant};
+
+extern crate failure;
+use failure::{Error, format_err};
 
 extern crate pathfinding;
 use pathfinding::prelude::astar;
 
-extern crate failure;
-use failure::Error;
+// The squares along a path and how long it is
+type Route = (Vec<Square>, usize);
+
+mod events;
+use crate::events::{Event, replay};
+
+mod rules;
+use crate::rules::{FactionRules, Rules};
+
+mod search;
+use crate::search::minimum_boost;
+
+mod stats;
+use crate::stats::{format_stats, unit_stats};
+
+mod view;
+use crate::view::{FrameFormat, dump_frames, watch};
 
-type Path = (Vec<Square>, usize);
+use grid::{Problem, Problems};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct UnitData {
+    id: usize,
     hp: i32,
     attack: i32,
     had_turn: bool,
 }
 
 impl UnitData {
-    fn new() -> UnitData {
-        UnitData { hp: 200, attack: 3, had_turn: false }
+    fn new(rules: &FactionRules) -> UnitData {
+        UnitData { id: 0, hp: rules.hp, attack: rules.attack, had_turn: false }
     }
 }
 
@@ -26,8 +51,8 @@ impl UnitData {
 enum SquareType {
     Open,
     Wall,
-    Elf,
-    Goblin
+    // A unit of the faction with the given index in the rules
+    Unit(usize),
 }
 use self::SquareType::*;
 
@@ -40,7 +65,7 @@ struct Square {
 }
 
 impl Square {
-    fn new(x: usize, y: usize, c: char) -> Square {
+    fn new(x: usize, y: usize, c: char, rules: &Rules) -> Square {
         match c {
             '.' => {
                 Square { x, y, t: Open, data: None }
@@ -48,28 +73,19 @@ impl Square {
             '#' => {
                 Square { x, y, t: Wall, data: None }
             },
-            'E' => {
-                Square { x, y, t: Elf, data: Some(UnitData::new()) }
+            _ => {
+                let faction = rules.find_glyph(c).expect("Unknown glyph");
+                let data = UnitData::new(&rules.factions[faction]);
+                Square { x, y, t: Unit(faction), data: Some(data) }
             },
-            'G' => {
-                Square { x, y, t: Goblin, data: Some(UnitData::new()) }
-            },
-            _ => unreachable!(),
         }
     }
-
-    fn distance(&self, other: &Square) -> usize {
-        ((self.x as i32 - other.x as i32).abs()
-            + (self.y as i32 - other.y as i32).abs()) as usize
-    }
 }
 
 impl PartialOrd for Square {
     // Order squares by reading order
     fn partial_cmp(&self, other: &Square) -> Option<Ordering> {
-        let s = self.x + (self.y * 10000);
-        let o = other.x + (other.y * 10000);
-        s.partial_cmp(&o)
+        Some(self.cmp(other))
     }
 }
 
@@ -82,14 +98,110 @@ impl Ord for Square {
     }
 }
 
+// How units decide where to move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Movement {
+    // A single breadth-first search out from the unit
+    FloodFill,
+    // A separate A* search for every square in range of a target, and then
+    // for every possible first step. This is how units used to move, kept to
+    // compare against.
+    PerTarget,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Turn {
+    Complete,
+    BattleOver,
+    // Stopped part way through the round
+    Stopped,
+}
+
 #[derive(Debug, Clone)]
 struct Map {
     map_vec: Vec<Square>,
     width: usize,
     height: usize,
+    // The number of complete rounds of the battle so far
+    rounds: usize,
+    // Everything that's happened in the battle so far
+    events: Vec<Event>,
+    movement: Movement,
+    rules: Rules,
 }
 
 impl Map {
+    // Read the map, reporting every problem with it instead of stopping at
+    // the first. Maps have to be surrounded by walls and every unit has to be
+    // able to get at an enemy, otherwise the battle would never end.
+    fn parse(input: &str, rules: Rules) -> Result<Map, Problems> {
+        let grid = grid::load(input, |c| c == '.' || c == '#' || rules.find_glyph(c).is_some())?;
+        let problems = grid.check_border('#');
+        if !problems.is_empty() {
+            return Err(Problems(problems));
+        }
+
+        let map_vec = grid.iter().map(|(x, y, c)| Square::new(x, y, c, &rules)).collect();
+        let mut map = Map { map_vec, width: grid.width, height: grid.height, rounds: 0,
+                            events: vec![], movement: Movement::FloodFill, rules };
+
+        // Number the units in reading order so we can tell them apart
+        for (id, u) in map.get_units_mut().into_iter().enumerate() {
+            u.data.as_mut().expect("Unit doesn't have data").id = id;
+        }
+
+        let problems = map.stranded_units();
+        if !problems.is_empty() {
+            return Err(Problems(problems));
+        }
+        Ok(map)
+    }
+
+    // A problem for each unit which can't reach anywhere to attack an enemy
+    // from, even once every other unit is out of the way. Needs the map to be
+    // surrounded by walls.
+    fn stranded_units(&self) -> Vec<Problem> {
+        // Label the squares a unit could ever get to from each other
+        let mut area = vec![None; self.map_vec.len()];
+        let mut areas = 0;
+        for start in 0..self.map_vec.len() {
+            if self.map_vec[start].t == Wall || area[start].is_some() {
+                continue;
+            }
+            area[start] = Some(areas);
+            let mut to_visit = vec![start];
+            while let Some(index) = to_visit.pop() {
+                for &(dx, dy) in self.neighbour_offsets() {
+                    let n = (index as isize + dx + dy * self.width as isize) as usize;
+                    if self.map_vec[n].t != Wall && area[n].is_none() {
+                        area[n] = Some(areas);
+                        to_visit.push(n);
+                    }
+                }
+            }
+            areas += 1;
+        }
+
+        let mut problems = vec![];
+        for u in self.get_units() {
+            let enemies = self.get_enemies(&u);
+            // With no enemies at all the battle's over straight away
+            if enemies.is_empty() {
+                continue;
+            }
+            let faction = self.rules.faction(&u.t);
+            let unit_area = area[u.x + self.width * u.y];
+            let can_attack = self.map_vec.iter()
+                .filter(|s| area[s.x + self.width * s.y] == unit_area)
+                .any(|s| enemies.iter().any(|e| self.rules.distance(s, e) <= faction.range));
+            if !can_attack {
+                problems.push(Problem::at(u.x, u.y, format!(
+                    "The {} '{}' can't reach any enemies", faction.name, faction.glyph)));
+            }
+        }
+        problems
+    }
+
     fn get(&self, x: usize, y :usize) -> Square {
         self.map_vec[x + (self.width * y)].clone()
     }
@@ -98,16 +210,21 @@ impl Map {
         &mut self.map_vec[x + (self.width * y)]
     }
 
+    // The offsets of the squares a unit can move to, in reading order
+    fn neighbour_offsets(&self) -> &'static [(isize, isize)] {
+        if self.rules.diagonal {
+            &[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)]
+        }
+        else {
+            &[(0, -1), (-1, 0), (1, 0), (0, 1)]
+        }
+    }
+
     fn get_neighbours(&self, square: &Square) -> Vec<Square> {
-        let x = square.x;
-        let y = square.y;
-        vec![
-            // Order the neighbours by reading order
-            self.get(x, y - 1),
-            self.get(x - 1, y),
-            self.get(x + 1, y),
-            self.get(x, y + 1),
-        ]
+        self.neighbour_offsets().iter()
+            .map(|&(dx, dy)| self.get((square.x as isize + dx) as usize,
+                                      (square.y as isize + dy) as usize))
+            .collect()
     }
 
     fn get_possible_moves(&self, square: &Square) -> Vec<(Square, usize)> {
@@ -118,15 +235,45 @@ impl Map {
         neighbours.iter().map(|s| (s.clone(), 1)).collect()
     }
 
+    // The units not allied with the given unit, in reading order
+    fn get_enemies(&self, square: &Square) -> Vec<Square> {
+        let mut enemies = self.get_units();
+        enemies.retain(|s| self.rules.are_enemies(&s.t, &square.t));
+        enemies
+    }
+
+    // The squares within the given range of a square, not including the
+    // square itself, in reading order
+    fn get_squares_in_range(&self, square: &Square, range: usize) -> Vec<Square> {
+        let x_range = square.x.saturating_sub(range)..=
+            square.x.saturating_add(range).min(self.width - 1);
+        let y_range = square.y.saturating_sub(range)..=
+            square.y.saturating_add(range).min(self.height - 1);
+        y_range.flat_map(|y| x_range.clone().map(move |x| (x, y)))
+            .filter(|&(x, y)| x != square.x || y != square.y)
+            .map(|(x, y)| self.get(x, y))
+            .filter(|s| self.rules.distance(s, square) <= range)
+            .collect()
+    }
+
+    // The enemies the unit can attack from where it is, in reading order
     fn get_targets_in_range(&self, square: &Square) -> Vec<Square> {
-        let enemy = match square.t {
-            Elf => Goblin,
-            Goblin => Elf,
-            _ => unreachable!(),
-        };
-        let mut neighbours = self.get_neighbours(square);
-        neighbours.retain(|s| s.t == enemy);
-        neighbours
+        let range = self.rules.faction(&square.t).range;
+        let mut targets = self.get_squares_in_range(square, range);
+        targets.retain(|s| s.data.is_some() && self.rules.are_enemies(&s.t, &square.t));
+        targets
+    }
+
+    // The injured allies the unit can heal from where it is, in reading order
+    fn get_allies_to_heal(&self, square: &Square) -> Vec<Square> {
+        let range = self.rules.faction(&square.t).range;
+        let mut allies = self.get_squares_in_range(square, range);
+        allies.retain(|s| match s.data {
+            Some(ref data) => !self.rules.are_enemies(&s.t, &square.t) &&
+                              data.hp < self.rules.faction(&s.t).hp,
+            None => false,
+        });
+        allies
     }
 
     // The returned units vector is in reading order
@@ -153,45 +300,154 @@ impl Map {
         units
     }
 
-    // The returned elves vector is in reading order
-    fn get_elves(&self) -> Vec<Square> {
-        let mut elves = self.get_units();
-        elves.retain(|s| s.t == Elf);
-        elves
+    // The returned units vector is in reading order
+    fn get_faction(&self, faction: usize) -> Vec<Square> {
+        let mut units = self.get_units();
+        units.retain(|s| s.t == Unit(faction));
+        units
+    }
+
+    // The returned units vector is in reading order
+    fn get_faction_mut(&mut self, faction: usize) -> Vec<&mut Square> {
+        let mut units_mut = self.get_units_mut();
+        units_mut.retain(|s| s.t == Unit(faction));
+        units_mut
+    }
+
+    // The id, position and HP of every unit in reading order
+    fn unit_summary(&self) -> Vec<(usize, usize, usize, i32)> {
+        self.get_units().iter().map(|u| {
+            let data = u.data.as_ref().unwrap();
+            (data.id, u.x, u.y, data.hp)
+        }).collect()
     }
 
-    // The returned elves vector is in reading order
-    fn get_elves_mut(&mut self) -> Vec<&mut Square> {
-        let mut elves_mut = self.get_units_mut();
-        elves_mut.retain(|s| s.t == Elf);
-        elves_mut
+    fn find_unit(&self, id: usize) -> Option<Square> {
+        self.get_units().into_iter().find(|u| u.data.as_ref().map(|d| d.id) == Some(id))
     }
 
-    // The returned goblins vector is in reading order
-    fn get_goblins(&self) -> Vec<Square> {
-        let mut goblins = self.get_units();
-        goblins.retain(|s| s.t == Goblin);
-        goblins
+    // The index in map_vec of each open square next to the given one. The
+    // indices are in reading order.
+    fn open_neighbours(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let width = self.width as isize;
+        self.neighbour_offsets().iter()
+            .map(move |&(dx, dy)| (index as isize + dx + dy * width) as usize)
+            .filter(move |&n| self.map_vec[n].t == Open)
     }
 
-    fn find_path(&self, start: &Square, dest: &Square) -> Option<Path> {
+    // Search outwards from the unit a layer at a time, remembering for each
+    // square the first step of the shortest paths to it which comes first in
+    // reading order. The first layer to reach a square in range then gives
+    // the target square, as the first in reading order within the layer, and
+    // the step to take towards it.
+    fn flood_fill_step(&self, unit: &Square, in_range: &[Square]) -> Option<Square> {
+        let mut is_in_range = vec![false; self.map_vec.len()];
+        for s in in_range {
+            is_in_range[s.x + self.width * s.y] = true;
+        }
+
+        let start = unit.x + self.width * unit.y;
+        let mut first_step = vec![None; self.map_vec.len()];
+        // Which layer each square was first reached in, counting from 1
+        let mut reached_in = vec![0; self.map_vec.len()];
+        let mut depth = 1;
+        let mut layer: Vec<_> = self.open_neighbours(start).collect();
+        for &s in &layer {
+            first_step[s] = Some(s);
+            reached_in[s] = depth;
+        }
+
+        while !layer.is_empty() {
+            if let Some(&target) = layer.iter().filter(|&&s| is_in_range[s]).min() {
+                return first_step[target].map(|s| self.map_vec[s].clone());
+            }
+
+            depth += 1;
+            let mut next_layer = vec![];
+            for &s in &layer {
+                let step = first_step[s];
+                for n in self.open_neighbours(s) {
+                    if n == start {
+                        continue;
+                    }
+                    match first_step[n] {
+                        None => {
+                            first_step[n] = step;
+                            reached_in[n] = depth;
+                            next_layer.push(n);
+                        }
+                        // Reached again in this layer by another route
+                        Some(existing) if reached_in[n] == depth => {
+                            first_step[n] = step.min(Some(existing));
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            layer = next_layer;
+        }
+        None
+    }
+
+    // The shortest path between two squares, if there is one, found with the
+    // pathfinding crate's A*
+    fn find_path(&self, start: &Square, dest: &Square) -> Option<Route> {
         astar(
             start,
             |s| self.get_possible_moves(s),
-            |s| s.distance(dest),
+            |s| self.rules.distance(s, dest),
             |s| s.x == dest.x && s.y == dest.y
         )
     }
 
+    // How units moved before flood_fill_step, with an A* search for every
+    // square in range of a target and then for every possible first step
+    fn per_target_step(&self, unit: &Square, in_range: &[Square]) -> Option<Square> {
+        // Refine to reachable squares
+        let targets: Vec<_> = in_range.iter()
+            .filter_map(|s| self.find_path(unit, s).map(|(_, d)| (s, d)))
+            .collect();
+
+        // Pick the closest target square, using reading order to break ties
+        // since in_range is sorted
+        let min_dist = targets.iter().map(|&(_, d)| d).min()?;
+        let target_square = targets.iter().find(|&&(_, d)| d == min_dist).unwrap().0;
+
+        // Now we've got a target square we consider each of the (up to)
+        // 4 possible next steps from our current position. We eliminate
+        // all squares except those satisfying the minimum distance to
+        // our target which we found earlier. The neighbours are in reading
+        // order so we can then just take the first one as our next square
+        // to move to.
+        self.get_possible_moves(unit)
+            .into_iter()
+            .map(|(s, _)| s)
+            .find(|s| self.find_path(s, target_square).map(|(_, d)| d) == Some(min_dist - 1))
+    }
+
     // Returns true if the battle finished during the turn, otherwise false
     fn take_turn(&mut self) -> bool {
+        self.take_turn_until(&|_| false) == Turn::BattleOver
+    }
+
+    // Play a round, stopping part way through if the given function returns
+    // true for anything that happens
+    fn take_turn_until(&mut self, stop: &dyn Fn(&Event) -> bool) -> Turn {
+        let round = self.rounds + 1;
         for u in self.get_units_mut() {
             u.data.as_mut().expect("Unit doesn't have data").had_turn = false;
         }
 
+        let mut checked = self.events.len();
 
         // MOVEMENT
         loop {
+            // Check what happened during the last unit's turn
+            if self.events[checked..].iter().any(stop) {
+                return Turn::Stopped;
+            }
+            checked = self.events.len();
+
             let mut next_square = None;
             let mut x = None;
             let mut y = None;
@@ -212,145 +468,117 @@ impl Map {
                 }
 
                 // Get a list of potential targets
-                let potential_targets = match unit.t {
-                    Elf => self.get_goblins(),
-                    Goblin => self.get_elves(),
-                    _ => unreachable!(),
-                };
+                let potential_targets = self.get_enemies(&unit);
 
                 // The battle ends if there's no potential targets left
                 if potential_targets.is_empty() {
-                    return true;
+                    let unit = unit.data.as_ref().unwrap().id;
+                    self.events.push(Event::End { round, unit });
+                    return Turn::BattleOver;
                 }
 
                 // Work out which squares are in range of a potential target
+                let range = self.rules.faction(&unit.t).range;
                 let mut in_range: Vec<_>
                     = potential_targets.iter()
-                                       .flat_map(|s| self.get_possible_moves(s))
-                                       .map(|(s, _)| s)
+                                       .flat_map(|s| self.get_squares_in_range(s, range))
+                                       .filter(|s| s.t == Open)
                                        .collect();
                 // Sort by reading order and remove duplicates
                 in_range.sort_unstable();
                 in_range.dedup();
 
-                // Refine to reachable squares
-                let mut targets: Vec<_> = in_range
-                                            .iter()
-                                            .map(|s| self.find_path(&unit, s))
-                                            .filter(|path| *path != None)
-                                            .map(|x| x.unwrap())
-                                            .collect();
-
-                // Bail out if there's no reachable targets
-                if targets.is_empty() {
-                    break;
-                }
-
-                // Refine to the (joint) closest target squares
-                let min_dist = match targets.iter().min_by_key(|(_, d)| d) {
-                    Some((_, d)) => *d,
-                    None => unreachable!(),
+                next_square = match self.movement {
+                    Movement::FloodFill => self.flood_fill_step(&unit, &in_range),
+                    Movement::PerTarget => self.per_target_step(&unit, &in_range),
                 };
-                targets.retain(|(_, d)| *d == min_dist);
-
-                // Sort targets by reading order (all paths end in the target so
-                // we can look at the end of a path to find the target)
-                targets.sort_unstable_by_key(
-                    |(path, _)| path[path.len() - 1].clone());
-
-                // Work out the target square we're trying to move towards
-                let (ref target_path, _) = targets[0];
-                let target_square = target_path.last().unwrap().clone();
-
-                // Now we've got a target square we consider each of the (up to)
-                // 4 possible next steps from our current position. We eliminate
-                // all squares except those satisfying the minimum distance to
-                // our target which we found earlier. The vector is sorted by
-                // reading order so we can then just take the first element as
-                // our next square to move to.
-                let possible_next_squares: Vec<_>
-                    = self.get_possible_moves(&unit)
-                        .iter()
-                        .map(|(s, _)| s.clone())
-                        .filter(|s| match self.find_path(s, &target_square) {
-                            None => false,
-                            Some((_, dist)) => dist + 1 == min_dist,
-                        })
-                        .collect();
-
-                next_square = Some(possible_next_squares[0].clone());
                 break;
             }
 
             // All units have taken turns so return
-            if x == None {
-                return false;
+            if x.is_none() {
+                self.rounds = round;
+                return Turn::Complete;
             }
 
             let unit = self.get_mut_ref(x.unwrap(), y.unwrap());
             unit.data.as_mut().unwrap().had_turn = true;
 
             // Actually move the unit on the map if appropriate
-            if next_square != None {
+            if let Some(ns) = next_square {
                 let unit_type = unit.t.clone();
                 let unit_data = unit.data.clone();
 
                 unit.t = Open;
                 unit.data = None;
 
-                // Explicitly drop [unit] since we're done with it now and need
-                // to take a new mut reference to self for [moved_unit]
-                std::mem::drop(unit);
-
-                let ns = next_square.unwrap();
                 let moved_unit = self.get_mut_ref(ns.x, ns.y);
                 moved_unit.t = unit_type;
                 moved_unit.data = unit_data;
 
+                let unit = moved_unit.data.as_ref().unwrap().id;
+                let from = (x.unwrap(), y.unwrap());
+                self.events.push(Event::Move { round, unit, from, to: (ns.x, ns.y) });
+
                 // Update x and y, they might be needed during attacking
                 x = Some(ns.x);
                 y = Some(ns.y);
             }
-            else {
-                std::mem::drop(unit);
-            }
 
 
             // ATTACK
             let u = self.get(x.unwrap(), y.unwrap());
-            let targets_in_range = self.get_targets_in_range(&u);
-
-            if targets_in_range.is_empty() {
-                continue;
-            }
-
-            let mut min_hp = 201;
-            let mut best_target = None;
-            for mut t in targets_in_range {
-                let hp = t.data.as_mut().expect("Unit has no data").hp;
-                if hp < min_hp {
-                    min_hp = hp;
-                    best_target = Some(t.clone());
+            let attacker = u.data.as_ref().unwrap();
+
+            // Go for the weakest target, the targets are in reading order so
+            // this picks the first of any with equal HP
+            let best_target = self.get_targets_in_range(&u).into_iter()
+                .min_by_key(|t| t.data.as_ref().expect("Unit has no data").hp);
+
+            if let Some(target) = best_target {
+                let target_mut = self.get_mut_ref(target.x, target.y);
+
+                // Do the attack
+                let damage = attacker.attack;
+                let target_data = target_mut.data.as_mut().unwrap();
+                target_data.hp -= damage;
+                let (id, hp) = (target_data.id, target_data.hp);
+
+                // Remove the victim if it has died
+                if hp <= 0 {
+                    target_mut.data = None;
+                    target_mut.t = Open;
                 }
-                else if hp == min_hp {
-                    // Should order by reading order because of Ord impl
-                    if t < best_target.clone().unwrap() {
-                        best_target = Some(t.clone());
-                    }
+
+                self.events.push(Event::Attack {
+                    round, unit: attacker.id, target: id, damage, hp
+                });
+                if hp <= 0 {
+                    self.events.push(Event::Death {
+                        round, unit: id, at: (target.x, target.y), killer: attacker.id
+                    });
                 }
             }
 
-            let target = best_target.expect("Error finding best target");
-            let target_mut = self.get_mut_ref(target.x, target.y);
-
-            // Do the attack
-            target_mut.data.as_mut().unwrap().hp
-                -= u.data.as_ref().unwrap().attack;
-
-            // Remove the victim if it has died
-            if target_mut.data.as_mut().unwrap().hp <= 0 {
-                target_mut.data = None;
-                target_mut.t = Open;
+            // HEALING
+            let heal = self.rules.faction(&u.t).heal;
+            if heal == 0 {
+                continue;
+            }
+            // The ally missing the most HP, the first in reading order if
+            // there's a tie
+            let patient = self.get_allies_to_heal(&u).into_iter()
+                .min_by_key(|a| {
+                    let missing = self.rules.faction(&a.t).hp - a.data.as_ref().unwrap().hp;
+                    std::cmp::Reverse(missing)
+                });
+            if let Some(patient) = patient {
+                let max_hp = self.rules.faction(&patient.t).hp;
+                let patient_data = self.get_mut_ref(patient.x, patient.y).data.as_mut().unwrap();
+                let amount = heal.min(max_hp - patient_data.hp);
+                patient_data.hp += amount;
+                let (target, hp) = (patient_data.id, patient_data.hp);
+                self.events.push(Event::Heal { round, unit: attacker.id, target, amount, hp });
             }
         }
     }
@@ -361,8 +589,7 @@ impl std::fmt::Display for Map {
         let printing_map_vec = self.map_vec.iter().map(|s| match s.t {
                                                             Open => '.',
                                                             Wall => '#',
-                                                            Elf => 'E',
-                                                            Goblin => 'G',
+                                                            Unit(f) => self.rules.factions[f].glyph,
                                                         }).collect::<Vec<_>>();
         let mut map_string = String::new();
         for row_index in 0..self.height {
@@ -379,16 +606,71 @@ impl std::fmt::Display for Map {
 fn main() -> Result<(), Error> {
     let input = fs::read_to_string("input.txt")?;
 
-    let height = input.lines().count();
-    let width = input.lines().next().unwrap().chars().count();
-
-    let mut map_vec = vec![];
-    for (y, line) in input.lines().enumerate() {
-        for (x, c) in line.chars().enumerate() {
-            map_vec.push(Square::new(x, y, c));
+    // Start with `--rules [file]` to fight with different rules, see rules.txt
+    let mut args: Vec<_> = env::args().collect();
+    let rules = if args.get(1).map(String::as_str) == Some("--rules") {
+        let path = args.get(2).ok_or_else(|| format_err!("No rules file given"))?;
+        let rules = Rules::parse(&fs::read_to_string(path)?)?;
+        args.drain(1..3);
+        rules
+    }
+    else {
+        Rules::default()
+    };
+    let starting_map = match Map::parse(&input, rules) {
+        Ok(map) => map,
+        Err(problems) => {
+            eprintln!("{}", problems);
+            process::exit(1);
+        }
+    };
+
+    // Pass `log` to print the events of the Part 1 battle as JSON lines,
+    // `replay [file] [n]` to rebuild the map after n rounds from such a log,
+    // `watch [ms]` to animate the battle in the terminal, `frames [dir] [ppm|txt]`
+    // to save every round of the battle as a numbered image or text file, or
+    // `bench` to compare the speed of the ways units can decide where to move
+    match args.get(1).map(String::as_str) {
+        Some("log") => {
+            let mut map = starting_map.clone();
+            while !map.take_turn() {}
+            for event in &map.events {
+                println!("{}", event);
+            }
+            return Ok(());
+        }
+        Some("replay") => {
+            let path = args.get(2).ok_or_else(|| format_err!("No log file given"))?;
+            let rounds = args.get(3).map_or(Ok(usize::MAX), |s| s.parse())?;
+            let events = fs::read_to_string(path)?.lines()
+                .map(|l| l.parse())
+                .collect::<Result<Vec<Event>, _>>()?;
+            let map = replay(&starting_map, &events, rounds)?;
+            println!("After {} rounds:\n{}", map.rounds, map);
+            return Ok(());
+        }
+        Some("watch") => {
+            let delay = args.get(2).map_or(Ok(100), |s| s.parse())?;
+            watch(&starting_map, Duration::from_millis(delay))?;
+            return Ok(());
+        }
+        Some("frames") => {
+            let dir = args.get(2).map_or("frames", String::as_str);
+            let format = match args.get(3).map(String::as_str) {
+                None | Some("ppm") => FrameFormat::Ppm,
+                Some("txt") => FrameFormat::Text,
+                Some(f) => return Err(format_err!("Unknown frame format '{}'", f)),
+            };
+            let frames = dump_frames(&starting_map, Path::new(dir), format)?;
+            println!("Wrote {} frames to {}", frames, dir);
+            return Ok(());
+        }
+        Some("bench") => {
+            benchmark(&starting_map);
+            return Ok(());
         }
+        _ => {}
     }
-    let starting_map = Map { map_vec, width, height };
 
 
     // Part 1
@@ -404,51 +686,77 @@ fn main() -> Result<(), Error> {
         println!("After {} rounds:\n{}", complete_rounds, part1_map);
     }
 
+    // Replaying all the battle's events should get us back to the same place
+    let replayed = replay(&starting_map, &part1_map.events, usize::MAX)?;
+    assert_eq!(replayed.rounds, complete_rounds as usize);
+    assert_eq!(replayed.to_string(), part1_map.to_string());
+    assert_eq!(replayed.unit_summary(), part1_map.unit_summary());
+
     let mut units = part1_map.get_units_mut();
-    let total_hp: i32 = units.iter_mut()
-                             .map(|u| u.data.as_mut().unwrap().hp)
+    let total_hp: i64 = units.iter_mut()
+                             .map(|u| u.data.as_mut().unwrap().hp as i64)
                              .sum();
     println!("The outcome of the battle for Part 1 is: {} * {} = {}\n",
-             complete_rounds, total_hp, complete_rounds * total_hp);
+             complete_rounds, total_hp, complete_rounds as i64 * total_hp);
+
+    // Every unit's share of the damage and healing should add up to what it
+    // lost
+    let stats = unit_stats(&starting_map, &part1_map.events);
+    for s in &stats {
+        let remaining = part1_map.find_unit(s.id).map_or(0, |u| u.data.unwrap().hp);
+        let hp = starting_map.rules.faction(&s.t).hp;
+        assert_eq!(s.damage_taken - s.healing_received + remaining, hp);
+    }
+    println!("How each unit fared:\n{}", format_stats(&stats, &starting_map.rules));
 
 
     // Part 2
-    let inital_elves = starting_map.get_elves().len();
-    for boost in 1..200 {
-        println!("Simulating a battle with attack boost {} for the elves...",
-                 boost);
-        let mut part2_map = starting_map.clone();
-
-        part2_map.get_elves_mut().iter_mut().for_each(|e| {
-            e.data.as_mut().unwrap().attack += boost;
-        });
-
-        let (outcome, remaining_elves) = resolve_battle(&part2_map);
-        if inital_elves == remaining_elves {
+    let elf = starting_map.rules.find_faction("elf").expect("There are always elves");
+    let elves: HashSet<_> = starting_map.get_faction(elf).iter()
+        .map(|e| e.data.as_ref().unwrap().id)
+        .collect();
+    let elf_died = |event: &Event| match *event {
+        Event::Death { unit, .. } => elves.contains(&unit),
+        _ => false,
+    };
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    match minimum_boost(&starting_map, elf, elf_died, threads) {
+        Some(result) => {
             println!("With an attack boost of {} the elves win without losses. \
-                        The outcome of this battle is {}.", boost, outcome);
-            break;
+                        The outcome of this battle is {} * {} = {}.", result.boost,
+                     result.rounds, result.total_hp, result.outcome);
         }
+        None => println!("The elves can't win without losses however much they're boosted."),
     }
 
     Ok(())
 }
 
-// Resolve a battle. Returning the outcome and the number of remaining elves
-fn resolve_battle(starting_map: &Map) -> (i32, usize) {
-    let mut map = starting_map.clone();
-    let mut complete_rounds = 0;
-    loop {
-        if map.take_turn() {
-            break;
-        };
-        complete_rounds += 1;
+// Run the Part 1 battle and the first few Part 2 battles with each kind of
+// movement, checking that they play out exactly the same
+fn benchmark(starting_map: &Map) {
+    const BOOSTS: i32 = 20;
+    let elf = starting_map.rules.find_faction("elf").expect("There are always elves");
+
+    let mut results = vec![];
+    for &movement in &[Movement::PerTarget, Movement::FloodFill] {
+        let start = Instant::now();
+        let mut events = vec![];
+        for boost in 0..=BOOSTS {
+            let mut map = starting_map.clone();
+            map.movement = movement;
+            map.get_faction_mut(elf).iter_mut().for_each(|e| {
+                e.data.as_mut().unwrap().attack += boost;
+            });
+            while !map.take_turn() {}
+            events.push(map.events);
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        println!("{:?}: {} battles in {:.3}s", movement, BOOSTS + 1, elapsed);
+        results.push((events, elapsed));
     }
 
-    let mut units = map.get_units_mut();
-    let total_hp: i32 = units.iter_mut()
-                             .map(|u| u.data.as_mut().unwrap().hp)
-                             .sum();
-
-    (total_hp * complete_rounds, map.get_elves().len())
+    assert_eq!(results[0].0, results[1].0);
+    println!("Speedup: {:.1}x", results[0].1 / results[1].1);
 }
+
@@ -0,0 +1,164 @@
+use std::convert::TryFrom;
+
+use failure::{Error, format_err};
+
+use crate::{Square, SquareType, SquareType::*};
+
+// The most HP, attack power or healing a faction can have. It keeps a unit's
+// HP well inside an i32 even with the biggest boost Part 2 tries, and the HP of
+// every unit times the rounds fought inside an i64.
+pub const MAX_POWER: i64 = 1_000_000;
+
+// How units of one faction fight
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FactionRules {
+    pub name: String,
+    // How the faction's units are shown on the map
+    pub glyph: char,
+    // Factions in the same alliance don't attack each other
+    pub alliance: String,
+    pub hp: i32,
+    pub attack: i32,
+    // How far away a unit can attack from, 1 means only adjacent squares
+    pub range: usize,
+    // How much HP a unit restores to an injured ally within range after it's
+    // attacked, 0 for none
+    pub heal: i32,
+}
+
+impl FactionRules {
+    fn new(name: &str, glyph: char) -> FactionRules {
+        FactionRules {
+            name: name.to_string(),
+            glyph,
+            alliance: name.to_string(),
+            hp: 200,
+            attack: 3,
+            range: 1,
+            heal: 0,
+        }
+    }
+}
+
+// Everything about a battle that isn't the map. The defaults are the rules
+// from the puzzle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rules {
+    pub factions: Vec<FactionRules>,
+    // Whether units can move and attack diagonally as well
+    pub diagonal: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            factions: vec![FactionRules::new("elf", 'E'), FactionRules::new("goblin", 'G')],
+            diagonal: false,
+        }
+    }
+}
+
+impl Rules {
+    pub fn faction(&self, t: &SquareType) -> &FactionRules {
+        match *t {
+            Unit(faction) => &self.factions[faction],
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn find_faction(&self, name: &str) -> Option<usize> {
+        self.factions.iter().position(|f| f.name == name)
+    }
+
+    pub fn find_glyph(&self, glyph: char) -> Option<usize> {
+        self.factions.iter().position(|f| f.glyph == glyph)
+    }
+
+    pub fn are_enemies(&self, a: &SquareType, b: &SquareType) -> bool {
+        self.faction(a).alliance != self.faction(b).alliance
+    }
+
+    // How many moves it takes to get between squares on an empty map
+    pub fn distance(&self, a: &Square, b: &Square) -> usize {
+        let dx = (a.x as i64 - b.x as i64).unsigned_abs() as usize;
+        let dy = (a.y as i64 - b.y as i64).unsigned_abs() as usize;
+        if self.diagonal { dx.max(dy) } else { dx + dy }
+    }
+
+    // Read rules from lines like `elf.attack = 10`. Anything not mentioned
+    // keeps its default, blank lines and lines starting with # are ignored.
+    // Setting the glyph of a faction we've not heard of adds a new faction.
+    pub fn parse(input: &str) -> Result<Rules, Error> {
+        let mut rules = Rules::default();
+        for (i, line) in input.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = parts.next()
+                .ok_or_else(|| format_err!("line {}: Expected 'key = value'", line_number))?
+                .trim();
+            let number = || value.parse::<i64>().map_err(|_| format_err!(
+                "line {}: Failed to parse '{}' as an integer", line_number, value));
+            let too_big = |_| format_err!("line {}: {} is too big", line_number, value);
+            let power = || match number()? {
+                n if n > MAX_POWER => Err(format_err!(
+                    "line {}: {} is too big, the most it can be is {}", line_number, n, MAX_POWER)),
+                n => Ok(n as i32),
+            };
+
+            if key == "diagonal" {
+                rules.diagonal = value.parse().map_err(|_| format_err!(
+                    "line {}: Expected true or false but found '{}'", line_number, value))?;
+                continue;
+            }
+
+            let mut key_parts = key.splitn(2, '.');
+            let name = key_parts.next().unwrap();
+            let setting = key_parts.next();
+            let index = match (rules.find_faction(name), setting) {
+                (Some(index), _) => index,
+                (None, Some("glyph")) => {
+                    rules.factions.push(FactionRules::new(name, '?'));
+                    rules.factions.len() - 1
+                }
+                (None, _) => return Err(format_err!(
+                    "line {}: Unknown faction '{}', give it a glyph first", line_number, name)),
+            };
+
+            let faction = &mut rules.factions[index];
+            match setting {
+                Some("glyph") => {
+                    let mut chars = value.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if c != '.' && c != '#' => faction.glyph = c,
+                        _ => return Err(format_err!(
+                            "line {}: '{}' can't be used as a glyph", line_number, value)),
+                    }
+                }
+                Some("alliance") => faction.alliance = value.to_string(),
+                Some("hp") if number()? > 0 => faction.hp = power()?,
+                Some("attack") if number()? >= 0 => faction.attack = power()?,
+                Some("range") if number()? > 0 =>
+                    faction.range = usize::try_from(number()?).map_err(too_big)?,
+                Some("heal") if number()? >= 0 => faction.heal = power()?,
+                Some(setting @ "hp") | Some(setting @ "attack") |
+                Some(setting @ "range") | Some(setting @ "heal") =>
+                    return Err(format_err!("line {}: {} can't be {}",
+                                           line_number, setting, value)),
+                _ => return Err(format_err!("line {}: Unknown setting '{}'", line_number, key)),
+            }
+
+            let glyph = rules.factions[index].glyph;
+            if rules.factions.iter().filter(|f| f.glyph == glyph).count() > 1 {
+                return Err(format_err!("line {}: Glyph '{}' is already used by another faction",
+                                       line_number, glyph));
+            }
+        }
+        Ok(rules)
+    }
+}
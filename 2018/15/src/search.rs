@@ -0,0 +1,86 @@
+use std::thread;
+
+use crate::{Map, SquareType::Unit, Turn};
+use crate::events::Event;
+
+// How a battle with a boosted faction turned out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoostResult {
+    pub boost: i32,
+    // The number of complete rounds
+    pub rounds: usize,
+    pub total_hp: i64,
+    // The number of complete rounds multiplied by the HP left
+    pub outcome: i64,
+}
+
+// Fight the battle with the faction's attack boosted, giving up as soon as
+// anything happens which fails the battle
+fn fight(starting_map: &Map, faction: usize, boost: i32,
+         fails: &(dyn Fn(&Event) -> bool + Sync)) -> Option<BoostResult> {
+    let mut map = starting_map.clone();
+    map.get_faction_mut(faction).iter_mut().for_each(|e| {
+        e.data.as_mut().unwrap().attack += boost;
+    });
+
+    loop {
+        match map.take_turn_until(fails) {
+            Turn::Complete => {}
+            Turn::BattleOver => break,
+            Turn::Stopped => return None,
+        }
+    }
+
+    let total_hp = map.get_units().iter().map(|u| u.data.as_ref().unwrap().hp as i64).sum();
+    Some(BoostResult {
+        boost,
+        rounds: map.rounds,
+        total_hp,
+        outcome: map.rounds as i64 * total_hp,
+    })
+}
+
+// Find the smallest boost to a faction's attack for which nothing happens
+// that fails the battle, e.g. an elf dying. This assumes a bigger boost never makes
+// things worse. Each step of the search fights a battle per thread, splitting
+// the range of boosts still in question evenly between them.
+pub fn minimum_boost<F>(starting_map: &Map, faction: usize, fails: F, threads: usize)
+    -> Option<BoostResult> where F: Fn(&Event) -> bool + Sync {
+    // With as much extra attack power as any enemy has HP the faction kills
+    // anything in one hit, so there's no point looking any higher
+    let rules = &starting_map.rules;
+    let max_boost = rules.factions.iter().enumerate()
+        .filter(|&(f, _)| rules.are_enemies(&Unit(f), &Unit(faction)))
+        .map(|(_, f)| f.hp)
+        .max()
+        .unwrap_or(0);
+    let mut best = fight(starting_map, faction, max_boost, &fails)?;
+
+    // Boosts up to lo fail, and hi = best.boost succeeds
+    let mut lo = 0;
+    while best.boost - lo > 1 {
+        let hi = best.boost;
+        let count = (threads.max(1) as i32).min(hi - lo - 1);
+        let mut boosts: Vec<_> = (1..=count).map(|i| lo + (hi - lo) * i / (count + 1)).collect();
+        boosts.dedup();
+
+        let fails = &fails;
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = boosts.iter()
+                .map(|&boost| scope.spawn(move || fight(starting_map, faction, boost, fails)))
+                .collect();
+            handles.into_iter().map(|h| h.join().expect("Battle panicked")).collect()
+        });
+
+        for (&boost, result) in boosts.iter().zip(results) {
+            match result {
+                Some(result) => {
+                    best = result;
+                    break;
+                }
+                None => lo = boost,
+            }
+        }
+    }
+    Some(best)
+}
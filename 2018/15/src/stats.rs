@@ -0,0 +1,74 @@
+use crate::{Map, SquareType};
+use crate::rules::Rules;
+use crate::events::Event;
+
+// What a single unit got up to during a battle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitStats {
+    pub id: usize,
+    pub t: SquareType,
+    pub start: (usize, usize),
+    // Damage only counts the HP actually lost, so overkill isn't included
+    pub damage_dealt: i32,
+    pub damage_taken: i32,
+    pub healing_done: i32,
+    pub healing_received: i32,
+    pub squares_walked: usize,
+    pub kills: usize,
+    // The round the unit died in, if it did
+    pub died: Option<usize>,
+}
+
+// Work out every unit's stats from the battle's events, in order of unit id
+pub fn unit_stats(start: &Map, events: &[Event]) -> Vec<UnitStats> {
+    let mut stats: Vec<_> = start.get_units().into_iter().map(|u| UnitStats {
+        id: u.data.as_ref().expect("Unit doesn't have data").id,
+        t: u.t.clone(),
+        start: (u.x, u.y),
+        damage_dealt: 0,
+        damage_taken: 0,
+        healing_done: 0,
+        healing_received: 0,
+        squares_walked: 0,
+        kills: 0,
+        died: None,
+    }).collect();
+    stats.sort_by_key(|s| s.id);
+
+    for event in events {
+        match *event {
+            Event::Move { unit, .. } => stats[unit].squares_walked += 1,
+            Event::Attack { unit, target, damage, hp, .. } => {
+                let lost = damage + hp.min(0);
+                stats[unit].damage_dealt += lost;
+                stats[target].damage_taken += lost;
+            }
+            Event::Heal { unit, target, amount, .. } => {
+                stats[unit].healing_done += amount;
+                stats[target].healing_received += amount;
+            }
+            Event::Death { round, unit, killer, .. } => {
+                stats[unit].died = Some(round);
+                stats[killer].kills += 1;
+            }
+            Event::End { .. } => {}
+        }
+    }
+    stats
+}
+
+// A table of the stats with one row per unit
+pub fn format_stats(stats: &[UnitStats], rules: &Rules) -> String {
+    let mut table = format!("{:>4}  {:<6}  {:>8}  {:>5}  {:>5}  {:>6}  {:>6}  {:>6}  {:>5}  {:>4}\n",
+                            "Unit", "Type", "Start", "Dealt", "Taken", "Heals", "Healed",
+                            "Walked", "Kills", "Died");
+    for s in stats {
+        let start = format!("{},{}", s.start.0, s.start.1);
+        let died = s.died.map_or("-".to_string(), |r| r.to_string());
+        table += &format!("{:>4}  {:<6}  {:>8}  {:>5}  {:>5}  {:>6}  {:>6}  {:>6}  {:>5}  {:>4}\n",
+                          s.id, rules.faction(&s.t).name, start, s.damage_dealt,
+                          s.damage_taken, s.healing_done, s.healing_received,
+                          s.squares_walked, s.kills, died);
+    }
+    table
+}
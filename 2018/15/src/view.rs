@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::{Map, SquareType::*};
+
+// How many characters wide the HP bars are
+const BAR_WIDTH: i64 = 10;
+
+// How many pixels across each square is in PPM frames
+const SCALE: usize = 8;
+
+// The colour for each faction, cycled through if there are lots of factions.
+// The ANSI code for terminals and the RGB colour for images.
+const PALETTE: [(u8, [u8; 3]); 6] = [
+    (32, [60, 200, 60]),
+    (31, [220, 50, 50]),
+    (34, [70, 110, 230]),
+    (33, [230, 200, 40]),
+    (35, [200, 70, 200]),
+    (36, [60, 200, 210]),
+];
+
+const WALL_RGB: [u8; 3] = [90, 90, 90];
+const OPEN_RGB: [u8; 3] = [20, 20, 20];
+
+fn colour(faction: usize) -> (u8, [u8; 3]) {
+    PALETTE[faction % PALETTE.len()]
+}
+
+// The map with each faction in its own colour and the HP of the units on each
+// row beside it, like the examples in the puzzle
+pub fn render_ansi(map: &Map) -> String {
+    let mut frame = String::new();
+    for y in 0..map.height {
+        let mut units = vec![];
+        for x in 0..map.width {
+            let square = map.get(x, y);
+            match (&square.t, &square.data) {
+                (Open, _) => frame.push('.'),
+                (Wall, _) => frame += "\x1b[2m#\x1b[0m",
+                (&Unit(f), Some(data)) => {
+                    let rules = &map.rules.factions[f];
+                    let code = colour(f).0;
+                    frame += &format!("\x1b[1;{}m{}\x1b[0m", code, rules.glyph);
+
+                    let (hp, max_hp) = (data.hp as i64, rules.hp as i64);
+                    let filled = (hp * BAR_WIDTH + max_hp - 1) / max_hp;
+                    let bar: String = (0..BAR_WIDTH).map(|i| if i < filled { '█' } else { '·' })
+                        .collect();
+                    units.push(format!("\x1b[{}m{}({:>3}) {}\x1b[0m",
+                                       code, rules.glyph, data.hp, bar));
+                }
+                (&Unit(_), None) => unreachable!(),
+            }
+        }
+        if !units.is_empty() {
+            frame += "   ";
+            frame += &units.join(", ");
+        }
+        // Clear anything left over from a longer line in the previous frame
+        frame += "\x1b[K\n";
+    }
+    frame
+}
+
+// The map as a binary PPM image. Units get darker as they lose HP.
+pub fn render_ppm(map: &Map) -> Vec<u8> {
+    let (width, height) = (map.width * SCALE, map.height * SCALE);
+    let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for py in 0..height {
+        for px in 0..width {
+            let square = map.get(px / SCALE, py / SCALE);
+            let rgb = match (&square.t, &square.data) {
+                (Open, _) => OPEN_RGB,
+                (Wall, _) => WALL_RGB,
+                (&Unit(f), Some(data)) => {
+                    let max_hp = map.rules.factions[f].hp;
+                    let fraction = 0.3 + 0.7 * data.hp.max(0) as f64 / max_hp as f64;
+                    let mut rgb = colour(f).1;
+                    rgb.iter_mut().for_each(|c| *c = (*c as f64 * fraction) as u8);
+                    rgb
+                }
+                (&Unit(_), None) => unreachable!(),
+            };
+            image.extend_from_slice(&rgb);
+        }
+    }
+    image
+}
+
+// Play the battle in the terminal, redrawing the map in place every round
+pub fn watch(starting_map: &Map, delay: Duration) -> io::Result<()> {
+    let mut map = starting_map.clone();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    // Clear the screen once, after that just go back to the top left corner
+    write!(out, "\x1b[2J")?;
+    loop {
+        write!(out, "\x1b[HRound {}\x1b[K\n{}", map.rounds, render_ansi(&map))?;
+        out.flush()?;
+        thread::sleep(delay);
+        if map.take_turn() {
+            break;
+        }
+    }
+    write!(out, "\x1b[HBattle over after {} complete rounds\x1b[K\n{}",
+           map.rounds, render_ansi(&map))?;
+    out.flush()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameFormat {
+    Ppm,
+    Text,
+}
+
+// Write out the map at the start of the battle and after every round as
+// numbered files in the directory, the last frame being the end of the battle.
+// Returns how many frames were written.
+pub fn dump_frames(starting_map: &Map, dir: &Path, format: FrameFormat) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+    let write_frame = |map: &Map, number: usize| match format {
+        FrameFormat::Ppm =>
+            fs::write(dir.join(format!("frame_{:05}.ppm", number)), render_ppm(map)),
+        FrameFormat::Text =>
+            fs::write(dir.join(format!("frame_{:05}.txt", number)), map.to_string()),
+    };
+
+    let mut map = starting_map.clone();
+    let mut frames = 0;
+    loop {
+        write_frame(&map, frames)?;
+        frames += 1;
+        if map.take_turn() {
+            break;
+        }
+    }
+    write_frame(&map, frames)?;
+    Ok(frames + 1)
+}
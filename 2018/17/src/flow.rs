@@ -0,0 +1,152 @@
+use std::collections::BTreeSet;
+
+use crate::{GroundScan, BlockType::*};
+
+// The points water is falling from which still need following, worked through
+// one at a time
+struct Falling {
+    points: BTreeSet<(i32, i32)>,
+}
+
+impl Falling {
+    fn new(scan: &mut GroundScan, springs: &[(i32, i32)]) -> Falling {
+        for &(x, y) in springs {
+            scan.set(x, y, Spring);
+        }
+        Falling { points: springs.iter().cloned().collect() }
+    }
+
+    // Follow the water from one falling point down and then out sideways,
+    // filling up any basin it lands in. Returns false once there's nothing
+    // left to follow.
+    fn step(&mut self, scan: &mut GroundScan) -> bool {
+        let key = match self.points.iter().next() {
+            Some(&key) => key,
+            None => return false,
+        };
+        let (x_source, y_source) = key;
+        let bottom = scan.y_max + 1;
+
+        // Find a block below and to start spreading out from
+        let mut j = 1;
+        loop {
+            if y_source + j >= bottom {
+                // We've hit the bottom of the map so stop resolving this source
+                self.points.remove(&key);
+                return true;
+            }
+
+            let block_below = scan.get(x_source, y_source + j);
+            match block_below {
+                Sand => scan.set(x_source, y_source + j, WettedSand),
+                Clay | Water => {
+                    // Go back up a level since we've hit something tha water
+                    // will sit on
+                    j -= 1;
+                    break;
+                },
+                // Water has already flowed here, so stop resolving this source
+                WettedSand | Spring => {
+                    self.points.remove(&key);
+                    return true;
+                },
+            }
+            j += 1;
+        }
+
+        // Start spreading out now that we're done falling down
+        loop {
+            scan.set(x_source, y_source + j, Water);
+
+            // Consider spreading sideways, first right and then left
+            let (i_max, right_edge_contained, right_falls) =
+                self.spread(scan, x_source, y_source + j, 1);
+            let (i_min, left_edge_contained, left_falls) =
+                self.spread(scan, x_source, y_source + j, -1);
+
+            // If we've found a new falling point then blocks on this level are
+            // where water flowed, not sat. It also means we've finished
+            // resolving this falling point
+            if right_falls || left_falls {
+                // Account for fact we have searched one block either side to
+                // find clay walls or falling points
+                for k in (i_min + 1)..i_max {
+                    scan.set(x_source + k, y_source + j, WettedSand);
+                }
+
+                self.points.remove(&key);
+                return true;
+            }
+
+            // If both edges are contained by clay walls then start filling up
+            // by spreading left and right again at a lower y value
+            if right_edge_contained && left_edge_contained {
+                j -= 1;
+            }
+            else {
+                return true;
+            }
+        }
+    }
+
+    // Spread water sideways from (x, y) a block at a time in the direction
+    // given by `step`. Returns how far it got, whether it hit a clay wall and
+    // whether it found somewhere to fall from.
+    fn spread(&mut self, scan: &mut GroundScan, x: i32, y: i32, step: i32) -> (i32, bool, bool) {
+        let mut i = step;
+        loop {
+            let block_side = scan.get(x + i, y);
+            match block_side {
+                Sand | WettedSand => {
+                    let block_below = scan.get(x + i, y + 1);
+                    match block_below {
+                        Sand => {
+                            scan.set(x + i, y, WettedSand);
+                            self.points.insert((x + i, y));
+                            return (i, false, true);
+                        },
+                        Clay | Water => scan.set(x + i, y, Water),
+                        WettedSand | Spring => return (i, false, false),
+                    }
+                },
+                Clay => return (i, true, false),
+                Water | Spring => return (i, false, false),
+            }
+            i += step;
+        }
+    }
+}
+
+// The scan after each step of the water flowing
+pub(crate) struct Steps {
+    scan: GroundScan,
+    falling: Falling,
+}
+
+impl Iterator for Steps {
+    type Item = GroundScan;
+
+    fn next(&mut self) -> Option<GroundScan> {
+        if self.falling.step(&mut self.scan) {
+            Some(self.scan.clone())
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl GroundScan {
+    // Let water flow from the springs until it's gone everywhere it can
+    pub fn fill(&mut self, springs: &[(i32, i32)]) {
+        let mut falling = Falling::new(self, springs);
+        while falling.step(self) {}
+    }
+
+    // Like `fill`, but one step at a time so the water can be watched as it
+    // goes
+    pub fn steps(mut self, springs: &[(i32, i32)]) -> Steps {
+        let falling = Falling::new(&mut self, springs);
+        Steps { scan: self, falling }
+    }
+}
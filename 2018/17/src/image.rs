@@ -0,0 +1,64 @@
+use crate::{GroundScan, BlockType, BlockType::*};
+
+fn colour(block: BlockType) -> [u8; 3] {
+    match block {
+        Clay => [120, 70, 30],
+        Sand => [235, 215, 160],
+        WettedSand => [120, 200, 255],
+        Water => [20, 60, 200],
+        Spring => [220, 30, 30],
+    }
+}
+
+// The part of the scan to draw, in puzzle coordinates with both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub x_min: i32,
+    pub x_max: i32,
+    pub y_min: i32,
+    pub y_max: i32,
+}
+
+impl Crop {
+    // Read a crop like `480,0,520,100`, as the top left and bottom right
+    // corners
+    pub fn parse(s: &str) -> Option<Crop> {
+        let numbers = s.split(',').map(|n| n.trim().parse().ok()).collect::<Option<Vec<i32>>>()?;
+        match numbers[..] {
+            [x_min, y_min, x_max, y_max] if x_min <= x_max && y_min <= y_max =>
+                Some(Crop { x_min, x_max, y_min, y_max }),
+            _ => None,
+        }
+    }
+}
+
+// The most pixels we'll draw, a scan with clay far apart needs cropping
+const MAX_PIXELS: u64 = 100_000_000;
+
+// The scan as a binary PPM image with each block `scale` pixels across. Parts
+// of the crop outside the scan are left out.
+pub fn render_ppm(scan: &GroundScan, crop: Option<Crop>, scale: usize)
+    -> Result<Vec<u8>, String> {
+    let x_min = crop.map_or(scan.x_min, |c| c.x_min.max(scan.x_min));
+    let y_min = crop.map_or(scan.y_min, |c| c.y_min.max(scan.y_min));
+    let x_max = crop.map_or(scan.x_max, |c| c.x_max.min(scan.x_max));
+    let y_max = crop.map_or(scan.y_max, |c| c.y_max.min(scan.y_max));
+    let blocks = |min: i32, max: i32| (max as i64 - min as i64 + 1).max(0) as u64;
+    let width = blocks(x_min, x_max).saturating_mul(scale as u64);
+    let height = blocks(y_min, y_max).saturating_mul(scale as u64);
+    if width.saturating_mul(height) > MAX_PIXELS {
+        return Err(format!("The image would be {}x{} pixels, crop it to something smaller",
+                           width, height));
+    }
+    let (width, height) = (width as usize, height as usize);
+
+    let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    image.reserve(width * height * 3);
+    for py in 0..height {
+        for px in 0..width {
+            let block = scan.get(x_min + (px / scale) as i32, y_min + (py / scale) as i32);
+            image.extend_from_slice(&colour(block));
+        }
+    }
+    Ok(image)
+}
@@ -1,9 +1,18 @@
+use std::collections::HashMap;
+use std::env;
 use std::fs;
-use std::ops::Range;
-use std::collections::BTreeSet;
 
-type ErrorHolder = Box<std::error::Error>;
-type PossibleRange = (i32, Option<i32>);
+mod flow;
+
+mod image;
+use crate::image::{Crop, render_ppm};
+
+mod vein;
+use crate::vein::{Vein, parse_veins};
+
+type ErrorHolder = Box<dyn std::error::Error>;
+
+const MAX_PRINTED: u64 = 10_000_000;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum BlockType {
@@ -15,312 +24,167 @@ enum BlockType {
 }
 use self::BlockType::*;
 
-#[derive(Debug)]
+// The ground goes on forever in every direction and the clay can be spread
+// far apart, so only the squares which aren't sand are stored. The x and y
+// ranges cover everything stored, anywhere else is sand.
+#[derive(Debug, Clone)]
 struct GroundScan {
-    grid: Vec<BlockType>,
+    blocks: HashMap<(i32, i32), BlockType>,
     x_min: i32,
+    x_max: i32,
     y_min: i32,
-    width: usize,
-    height: usize,
+    // The lowest row of the scan, water falling past it is gone for good
+    y_max: i32,
 }
 
 impl GroundScan {
-    fn new(x_min: i32, y_min: i32, width: usize, height: usize) -> GroundScan {
-        let (y_min, height) = if y_min > 0 {
-            (0, height + (y_min as usize))
-        } else {
-            (y_min, height)
-        };
-        let grid = vec![Sand; width * height];
-        let mut gs = GroundScan { grid, x_min, y_min, width, height };
+    fn new(x_min: i32, x_max: i32, y_min: i32, y_max: i32) -> GroundScan {
+        GroundScan { blocks: HashMap::new(), x_min, x_max, y_min, y_max }
+    }
 
-        // Add the spring
-        gs.set(500, 0, Spring);
-        gs
+    // How many squares the x and y ranges cover
+    fn area(&self) -> u64 {
+        let span = |min: i32, max: i32| (max as i64 - min as i64 + 1) as u64;
+        span(self.x_min, self.x_max) * span(self.y_min, self.y_max)
     }
 
-    fn add_clay(&mut self, x_range: Range<i32>, y_range: Range<i32>) {
-        for x in x_range {
-            for y in y_range.clone() {
+    fn add_clay(&mut self, vein: &Vein) {
+        for x in vein.x.0..=vein.x.1 {
+            for y in vein.y.0..=vein.y.1 {
                 self.set(x, y, Clay);
             }
         }
     }
 
     fn get(&self, x: i32, y: i32) -> BlockType {
-        let x_i = (x - self.x_min) as usize;
-        let y_i = (y - self.y_min) as usize;
-        self.grid[x_i + (self.width * y_i)]
+        self.blocks.get(&(x, y)).cloned().unwrap_or(Sand)
     }
 
     fn set(&mut self, x: i32, y: i32, value: BlockType) {
-        let x_i = (x - self.x_min) as usize;
-        let y_i = (y - self.y_min) as usize;
-        self.grid[x_i + (self.width * y_i)] = value;
-    }
-}
-
-impl std::fmt::Display for GroundScan {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let printing_grid = self.grid.iter().map(|b| match b {
-                                                Clay => '#',
-                                                Sand => '.',
-                                                WettedSand => '|',
-                                                Water => '~',
-                                                Spring => '+',
-                                            }).collect::<Vec<_>>();
-        let mut grid_string = String::new();
-        for row_index in 0..self.height {
-            let mut row: String = printing_grid.iter()
-                                    .skip(row_index * self.width)
-                                    .take(self.width).collect();
-            row.push('\n');
-            grid_string.push_str(&row);
+        self.x_min = self.x_min.min(x);
+        self.x_max = self.x_max.max(x);
+        self.y_min = self.y_min.min(y);
+        self.y_max = self.y_max.max(y);
+        if value == Sand {
+            self.blocks.remove(&(x, y));
+        }
+        else {
+            self.blocks.insert((x, y), value);
         }
-        write!(f, "{}", grid_string)
     }
-}
 
-fn s_to_i(s: &str) -> i32 {
-    s.parse().expect("Failed to parse str as i32")
+    // How many blocks at or below the given y value match
+    fn count_from<F>(&self, y: i32, matches: F) -> usize where F: Fn(BlockType) -> bool {
+        self.blocks.iter().filter(|&(&(_, block_y), &b)| block_y >= y && matches(b)).count()
+    }
 }
 
-fn parse_possible_range(s: &str) -> PossibleRange {
-    match s.contains("..") {
-        true => {
-            let split: Vec<_> = s.split("..").map(s_to_i).collect();
-            assert_eq!(split.len(), 2);
-            (split[0], Some(split[1]))
-        },
-        false => {
-            (s_to_i(s), None)
+impl std::fmt::Display for GroundScan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for y in self.y_min..=self.y_max {
+            let row: String = (self.x_min..=self.x_max).map(|x| match self.get(x, y) {
+                                                            Clay => '#',
+                                                            Sand => '.',
+                                                            WettedSand => '|',
+                                                            Water => '~',
+                                                            Spring => '+',
+                                                        }).collect();
+            writeln!(f, "{}", row)?;
         }
+        Ok(())
     }
 }
 
-fn parse_x_and_y(line: &str) -> (PossibleRange, PossibleRange) {
-    let split: Vec<_> =  line.split(", ").collect();
-    assert_eq!(split.len(), 2);
-
-    // Determine if x or y is given first
-    let (x_index, y_index) = match split[0].contains("x") {
-        true => (0, 1),
-        false => (1, 0),
-    };
-
-    let x = parse_possible_range(&split[x_index][2..]);
-    let y = parse_possible_range(&split[y_index][2..]);
-    (x, y)
-}
-
-// This is a very ugly function... I should have made a PossibleRange struct...
-fn get_grid_limits(clay: &Vec<(PossibleRange, PossibleRange)>) ->
-                                                        (i32, i32, i32, i32) {
-    let err_str = "Failed to find grid limit";
-
-    let ((x_min, _), (_, _)) =
-        clay.iter().min_by_key(|((x, _), (_, _))| x).expect(err_str);
-    let ((_, _), (y_min, _)) =
-        clay.iter().min_by_key(|((_, _), (y, _))| y).expect(err_str);
-
-    let ((x_l_max, _), (_, _)) =
-        clay.iter().max_by_key(|((x, _), (_, _))| x).expect(err_str);
-    let ((_, _), (y_l_max, _)) =
-        clay.iter().max_by_key(|((_, _), (y, _))| y).expect(err_str);
-
-    let ((_, x_r_max_option), (_, _)) =
-        clay.iter()
-        .max_by_key(|((_, x), (_, _))| match *x { None => 0, Some(x) => x })
-        .expect(err_str);
-    let ref x_r_max = x_r_max_option.unwrap();
-    let ((_, _), (_, y_r_max_option)) =
-        clay.iter()
-        .max_by_key(|((_, _), (_, y))| match *y { None => 0, Some(y) => y })
-        .expect(err_str);
-    let ref y_r_max = y_r_max_option.unwrap();
-
-    let x_max = if x_l_max > x_r_max { x_l_max } else { x_r_max };
-    let y_max = if y_l_max > y_r_max { y_l_max } else { y_r_max };
-
-    (*x_min, *x_max, *y_min, *y_max)
-}
-
 fn main() -> Result<(), ErrorHolder> {
     let input = fs::read_to_string("input.txt")?;
 
-    let mut clay = vec![];
-    for ref line in input.lines() {
-        clay.push(parse_x_and_y(line));
+    // Start with `--spring x,y` to put a spring somewhere other than 500,0,
+    // give it more than once for more springs
+    let mut args: Vec<_> = env::args().collect();
+    let mut springs = vec![];
+    while args.get(1).map(String::as_str) == Some("--spring") {
+        let spring = args.get(2).ok_or("No spring given")?;
+        let mut coords = spring.split(',').map(|c| c.trim().parse::<i32>());
+        match (coords.next(), coords.next(), coords.next()) {
+            (Some(Ok(x)), Some(Ok(y)), None) => springs.push((x, y)),
+            _ => return Err(format!("Expected a spring like 500,0 but found '{}'", spring).into()),
+        }
+        args.drain(1..3);
     }
-
-    let (x_min, x_max, y_min, y_max) = get_grid_limits(&clay);
-    let width = (1 + x_max - x_min) as usize;
-    let height = (1 + y_max - y_min) as usize;
-
-    // Add some padding in the x direction
-    let mut scan = GroundScan::new(x_min - 1, y_min, width + 2, height);
-    for c in clay {
-        let ((x1, x2_option), (y1, y2_option)) = c;
-
-        let x2 = match x2_option {
-            Some(x) =>  x + 1,
-            None => x1 + 1,
-        };
-        let y2 = match y2_option {
-            Some(y) =>  y + 1,
-            None => y1 + 1,
-        };
-
-        scan.add_clay(x1..x2, y1..y2);
+    if springs.is_empty() {
+        springs.push((500, 0));
     }
 
-    let mut water_falling_points = BTreeSet::new();
-    water_falling_points.insert((500, 0));
-
-    'outer: loop {
-        if  water_falling_points.len() == 0 {
-            break;
+    let clay = match parse_veins(&input) {
+        Ok(clay) => clay,
+        Err(e) => {
+            eprintln!("Failed to read the scan, {}", e);
+            std::process::exit(1);
         }
+    };
+    let x_min = clay.iter().map(|v| v.x.0).min().unwrap();
+    let x_max = clay.iter().map(|v| v.x.1).max().unwrap();
+    let y_min = clay.iter().map(|v| v.y.0).min().unwrap();
+    let y_max = clay.iter().map(|v| v.y.1).max().unwrap();
 
-        // Clone so we don't keep an immutable reference to water_falling_points
-        let key = water_falling_points.iter().next().unwrap().clone();
-        let (x_source, y_source) = key;
+    // The scan has to reach up to the highest spring as well as the clay
+    let top = springs.iter().map(|&(_, y)| y).min().unwrap_or(y_min).min(y_min);
 
-        // Find a block below and to start spreading out from
-        let mut j = 1;
-        loop {
-            if j == (scan.height as i32) - scan.y_min - y_source {
-                // We've hit the bottom of the map so stop resolving this source
-                water_falling_points.remove(&key);
-                continue 'outer;
-            }
+    // Add some padding in the x direction
+    let mut scan = GroundScan::new(x_min.saturating_sub(1), x_max.saturating_add(1), top, y_max);
+    for vein in &clay {
+        scan.add_clay(vein);
+    }
 
-            let block_below = scan.get(x_source, y_source + j);
-            match block_below {
-                Sand => scan.set(x_source, y_source + j, WettedSand),
-                Clay | Water => {
-                    // Go back up a level since we've hit something tha water
-                    // will sit on
-                    j -= 1;
-                    break;
-                },
-                // Water has already flowed here, so stop resolving this source
-                WettedSand => {
-                    water_falling_points.remove(&key);
-                    continue 'outer;
-                },
-                _ => unreachable!(),
+    match args.get(1).map(String::as_str) {
+        // Pass `steps` to see how far the water's got after each step
+        Some("steps") => {
+            for (i, step) in scan.steps(&springs).enumerate() {
+                println!("After step {} the water reaches {} tiles", i + 1,
+                         step.count_from(y_min, |b| b == Water || b == WettedSand));
             }
-            j += 1;
+            return Ok(());
         }
+        Some("image") => {}
+        Some(arg) => return Err(format!("Unknown argument '{}'", arg).into()),
+        None => {}
+    }
 
-        // Start spreading out now that we're done falling down
-        loop {
-            scan.set(x_source, y_source + j, Water);
-
-            // Consider spreading sideways
-            // First spread right
-            let mut new_falling_point = false;
-            let mut right_edge_contained = false;
-            let mut i = 1;
-            loop {
-                let block_right = scan.get(x_source + i, y_source + j);
-                match block_right {
-                    Sand | WettedSand => {
-                        let block_below = scan.get(x_source + i, y_source + j + 1);
-                        match block_below {
-                            Sand => {
-                                scan.set(x_source + i, y_source + j, WettedSand);
-                                water_falling_points.insert((x_source + i, y_source + j));
-                                new_falling_point = true;
-                                break;
-                            },
-                            Clay | Water => scan.set(x_source + i, y_source + j, Water),
-                            WettedSand => break,
-                            _ => unreachable!(),
-                        }
-                    },
-                    Clay => {
-                        right_edge_contained = true;
-                        break;
-                    },
-                    Water => break,
-                    _ => unreachable!(),
-                }
-                i += 1;
-            }
-            let i_max = i;
-
-            // Then spread left
-            let mut left_edge_contained = false;
-            i = -1;
-            loop {
-                let block_left = scan.get(x_source + i, y_source + j);
-                match block_left {
-                    Sand | WettedSand => {
-                        let block_below = scan.get(x_source + i, y_source + j + 1);
-                        match block_below {
-                            Sand => {
-                                scan.set(x_source + i, y_source + j, WettedSand);
-                                water_falling_points.insert((x_source + i, y_source + j));
-                                new_falling_point = true;
-                                break;
-                            },
-                            Clay | Water => scan.set(x_source + i, y_source + j, Water),
-                            WettedSand => break,
-                            _ => unreachable!(),
-                        }
-                    },
-                    Clay => {
-                        left_edge_contained = true;
-                        break;
-                    },
-                    Water => break,
-                    _ => unreachable!(),
-                }
-                i -= 1;
-            }
-            let i_min = i;
-
-            // If we've found a new falling point then blocks on this level are
-            // where water flowed, not sat. It also means we've finished
-            // resolving this falling point so exit the loop
-            if new_falling_point {
-                // Account for fact we have searched one block either side to
-                // find clay walls or falling points
-                for k in (i_min + 1)..i_max {
-                    scan.set(x_source + k, y_source + j, WettedSand);
-                }
-
-                water_falling_points.remove(&key);
-                continue 'outer;
-            }
+    scan.fill(&springs);
 
-            // If both edges are contained by clay walls then start filling up
-            // by spreading left and right again at a lower y value
-            if right_edge_contained && left_edge_contained {
-                j -= 1;
-            }
-            else {
-                break;
-            }
+    // Pass `image [file] [scale] [x1,y1,x2,y2]` to save the filled scan as a
+    // PPM image instead of printing it, optionally zoomed in on part of it
+    if args.get(1).map(String::as_str) == Some("image") {
+        let path = args.get(2).map_or("scan.ppm", String::as_str);
+        let scale = args.get(3).map_or(Ok(1), |s| s.parse::<usize>())?;
+        if scale == 0 {
+            return Err("The scale has to be at least 1".into());
         }
+        let crop = match args.get(4) {
+            Some(c) => Some(Crop::parse(c).ok_or_else(|| format!(
+                "Expected a crop like 480,0,520,100 but found '{}'", c))?),
+            None => None,
+        };
+        fs::write(path, render_ppm(&scan, crop, scale)?)?;
+        println!("Wrote the scan to {}", path);
+        return Ok(());
     }
 
-    println!("{}", scan);
-
-    // scan.y_min may be lower than y_min from the input in order to fit the
-    // initial spring on the grid. When counting up the water for the answers
-    // we don't inlcude anything with y coordinate less than the miniumum y
-    // value from the input so we may need to skip a few rorws of the grid
-    let y_skip = (y_min - scan.y_min) as usize;
+    // Clay spread far apart makes a scan which is mostly sand and too big to
+    // print, the answers are still worked out
+    if scan.area() <= MAX_PRINTED {
+        println!("{}", scan);
+    }
+    else {
+        println!("The scan is too big to print, pass `image` with a crop to see part of it");
+    }
 
-    let part1_count = scan.grid.iter().skip(y_skip * scan.width)
-                        .filter(|&&b| b == Water || b == WettedSand).count();
+    // When counting up the water for the answers we don't include anything
+    // with y coordinate less than the miniumum y value from the input
+    let part1_count = scan.count_from(y_min, |b| b == Water || b == WettedSand);
     println!("The water reaches {} tiles!", part1_count);
 
-    let part2_count = scan.grid.iter().skip(y_skip * scan.width)
-                        .filter(|&&b| b == Water).count();
+    let part2_count = scan.count_from(y_min, |b| b == Water);
     println!("{} water tiles are left when the spring dries up!", part2_count);
 
     Ok(())
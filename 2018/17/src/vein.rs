@@ -0,0 +1,113 @@
+// A vein of clay from one line of the scan. Both ends of each range are
+// included, so a single square has the same start and end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vein {
+    pub x: (i32, i32),
+    pub y: (i32, i32),
+}
+
+impl Vein {
+    // How many squares two veins have in common
+    fn overlap(&self, other: &Vein) -> i64 {
+        let common = |a: (i32, i32), b: (i32, i32)| (a.1.min(b.1) - a.0.max(b.0) + 1).max(0) as i64;
+        common(self.x, other.x) * common(self.y, other.y)
+    }
+}
+
+fn format_range(f: &mut std::fmt::Formatter, (start, end): (i32, i32)) -> std::fmt::Result {
+    if start == end { write!(f, "{}", start) } else { write!(f, "{}..{}", start, end) }
+}
+
+impl std::fmt::Display for Vein {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "x=")?;
+        format_range(f, self.x)?;
+        write!(f, ", y=")?;
+        format_range(f, self.y)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn parse_number(s: &str) -> Result<i32, String> {
+    s.trim().parse().map_err(|_| format!("Expected a number but found '{}'", s.trim()))
+}
+
+// A single value like `495` or a range like `2..7`
+fn parse_range(s: &str) -> Result<(i32, i32), String> {
+    let mut ends = s.splitn(2, "..");
+    let start = parse_number(ends.next().unwrap())?;
+    let end = match ends.next() {
+        Some(end) => parse_number(end)?,
+        None => start,
+    };
+    if start > end {
+        return Err(format!("The range {}..{} goes backwards", start, end));
+    }
+    Ok((start, end))
+}
+
+// A line like `x=495, y=2..7`, with the axes either way round
+fn parse_vein(line: &str) -> Result<Vein, String> {
+    let (mut x, mut y) = (None, None);
+    let parts: Vec<_> = line.split(',').collect();
+    if parts.len() != 2 {
+        return Err(format!("Expected an x and a y separated by a comma but found '{}'", line));
+    }
+    for part in parts {
+        let mut sides = part.splitn(2, '=');
+        let name = sides.next().unwrap().trim();
+        let axis = match name {
+            "x" => &mut x,
+            "y" => &mut y,
+            _ => return Err(format!("Expected x or y but found '{}'", name)),
+        };
+        let value = sides.next()
+            .ok_or_else(|| format!("Expected something like x=495 but found '{}'", part.trim()))?;
+        if axis.is_some() {
+            return Err(format!("{} is given twice", name));
+        }
+        *axis = Some(parse_range(value)?);
+    }
+    match (x, y) {
+        (Some(x), Some(y)) => Ok(Vein { x, y }),
+        (None, _) => Err("x is missing".to_string()),
+        (_, None) => Err("y is missing".to_string()),
+    }
+}
+
+// Read every vein of clay from the scan, one per line. Blank lines are skipped.
+// Veins can share a square, like at the corners of basins, but two veins
+// which overlap any more than that are probably a mistake.
+pub fn parse_veins(input: &str) -> Result<Vec<Vein>, ParseError> {
+    let mut veins: Vec<(usize, Vein)> = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let line_number = i + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let vein = parse_vein(line)
+            .map_err(|message| ParseError { line: line_number, message })?;
+        if let Some(&(other_line, other)) = veins.iter().find(|(_, v)| v.overlap(&vein) > 1) {
+            return Err(ParseError { line: line_number, message: format!(
+                "{} overlaps {} from line {}", vein, other, other_line) });
+        }
+        veins.push((line_number, vein));
+    }
+    if veins.is_empty() {
+        return Err(ParseError { line: 1, message: "There's no clay in the scan".to_string() });
+    }
+    Ok(veins.into_iter().map(|(_, v)| v).collect())
+}
@@ -1,197 +1,155 @@
+use std::env;
+use std::fs;
+use std::io;
 use std::collections::HashSet;
+use std::time::Instant;
 
-type ErrorHolder = Box<std::error::Error>;
+extern crate elfcode;
+use elfcode::{Arithmetic, Compiled, ControlFlowGraph, Debugger, Opcode, Processor, Program, Stop};
 
-fn main() -> Result<(), ErrorHolder> {
-    // *Part 1* logic (calculate solution later on with part 2)
-    //
-    // Only command 28 makes use of the [0], since we can only effect
-    // [0] the program up until the point where command 28 runs is out of our
-    // control so don't bother trying to understand it.
-    // State when we first run command 28: [x, 28, 1, 13270004, 1, 1]
-    //
-    // Carrying on:
-    // IP is now 28 so set [2] to [3]==[0] (false with x = 0)
-    // IP is now 29 so add [2] to [1]
-    // IP is now 30 so set [1] to 5
-    // IP is now 6...
-    //
-    // Now if [3]==[0] was true then the program would have had IP 31 after
-    // command 29 and finished. This is surely the quickest way to halt the
-    // program by changing only the inital value of [0].
-    //
-    // So the answer to part 1 us 13270004!
+type ErrorHolder = Box<dyn std::error::Error>;
 
+// The only place the program reads register [0] is a single `eqrr` which
+// compares it against another register and jumps past the end of the program
+// if they're equal. Find that command and the register [0] is compared with.
+fn find_halting_check(program: &Program) -> Option<(usize, i32)> {
+    program.commands.iter().enumerate().find_map(|(i, command)| {
+        match (command.opcode, command.a, command.b) {
+            (Opcode::Eqrr, 0, other) | (Opcode::Eqrr, other, 0) => Some((i, other)),
+            _ => None,
+        }
+    })
+}
 
+fn new_processor(program: &Program) -> Processor<i64> {
+    Processor::with_ip_register(program.register_count, program.ip_register)
+        .with_arithmetic(Arithmetic::Checked)
+}
 
-    // *Part 2*
-    //
-    // We need to understand the program now, so running through it:
-    //
-    // IP is [1]
-    // IP is now 0 so set [3] to 123
-    // IP is now 1 so set [3] to [3]&456 (binary and)
-    // IP is now 2 so set [3] to [3]==72 (true for this code)
-    // IP is now 3 so add [3] to [1]
-    // IP is now 5 so set [3] to 0
-    //
-    // Everything so far is the check that inputs are being interpreted as
-    // numbers and not strings. Once complete, there's no resulting changes.
-    //
-    // IP is now 6 so set [5] to [3]|65536
-    // IP is now 7 so set [3] to 15028787
-    // IP is now 8 so set [2] to [5]&255
-    // IP is now 9 so add [2] to [3]
-    // IP is now 10 so set [3] to [3]&16777215
-    // IP is now 11 so multiply [3] by 65899
-    // IP is now 12 so set [3] to [3]&16777215
-    // IP is now 13 so set [2] to 256>[5] (false)
-    // IP is now 14 so add [2] to [1]
-    // IP is now 15 so add 1 to [1]
-    // IP is now 17 so set [2] to 0
-    // >> LOOP <<
-    // IP is now 18 so set [4] to [2]+1
-    // IP is now 19 so multiply [4] by 256
-    // IP is now 20 so set [4] to [4]>[5] (false for now)
-    // IP is now 21 so add [4] to [1]
-    // IP is now 22 so add 1 to [1]
-    // IP is now 24 so add 1 to [2]
-    // IP is now 25 so set [1] to 17
-    // >> LOOP <<
-    // IP is now 18 so...
-
-    // The above loop doesn't involve [0] (the one thing we can control) so it's
-    // not helpful towards the solution but we still need to get past it.
-    // The only test is that [4]>[5] where [4]=256*([2]+1) and every loop [2]
-    // increases by 1. The condition will be true when [2] = 256 at the start
-    // of the loop. So we start with [x, 18, 256, 6196817, 0, 65536] and do:
-    //
-    // IP is now 18 so set [4] to [2]+1
-    // IP is now 19 so multiply [4] by 256
-    // IP is now 20 so set [4] to [4]>[5] (true)
-    // IP is now 21 so add [4] to [1]
-    // IP is now 23 so set [1] to 25
-    // IP is now 26 so set [5] to [2]
-    // IP is now 27 so set [1] to 7
-    //
-    // IP is now 8 so set [2] to [5]&255
-    // IP is now 9 so add [2] to [3]
-    // IP is now 10 so set [3] to [3]&16777215
-    // IP is now 11 so multiply [3] by 65899
-    // IP is now 12 so set [3] to [3]&16777215
-    // IP is now 13 so set [2] to 256>[5] (false)
-    // IP is now 14 so add [2] to [1]
-    // IP is now 15 so add 1 to [1]
-    // IP is now 17 so set [2] to 0
-    //
-    // IP is now 18 so set [4] to [2]+1
-    // IP is now 19 so multiply [4] by 256
-    // IP is now 20 so set [4] to [4]>[5] (false for now)
-    // IP is now 21 so add [4] to [1]
-    // IP is now 22 so add 1 to [1]
-    // IP is now 24 so add 1 to [2]
-    // IP is now 25 so set [1] to 17
-    //
-    // IP is now 18 so set [4] to [2]+1
-    // IP is now 19 so multiply [4] by 256
-    // IP is now 20 so set [4] to [4]>[5] (now true)
-    // IP is now 21 so add [4] to [1]
-    // IP is now 23 so set [1] to 25
-    // IP is now 26 so set [5] to [2]
-    // IP is now 27 so set [1] to 7
-    //
-    // IP is now 8 so set [2] to [5]&255
-    // IP is now 9 so add [2] to [3]
-    // IP is now 10 so set [3] to [3]&16777215
-    // IP is now 11 so multiply [3] by 65899
-    // IP is now 12 so set [3] to [3]&16777215
-    // IP is now 13 so set [2] to 256>[5] (now true)
-    // IP is now 14 so add [2] to [1]
-    // IP is now 16 so set [1] to 27
-    //
-    // Now IP 28 where we have to consider [0], the state is:
-    // [x, 28, 1, 13270004, 1, 1]
+// Time the plain interpreter against the compiled program over the first few
+// thousand passes through the halting check
+fn benchmark(program: &Program, check_index: usize) -> Result<(), ErrorHolder> {
+    const CHECKS: usize = 2000;
 
-    // Loop 1 (18-25):
-    //
-    // loop {
-        // if 256*([2]+1) > [5] {
-            // break to command 26
-        // }
-        // [2]++
-    // }
-    //
-    // Command 26 sets [5] to [2] and goes to the start of loop 2 (see below)
+    let mut processor = new_processor(program);
+    let mut steps = 0u64;
+    let mut checks = 0;
+    let start = Instant::now();
+    while checks < CHECKS {
+        processor.step(&program.commands)?;
+        steps += 1;
+        if processor.ip() == check_index as i64 {
+            checks += 1;
+        }
+    }
+    let interpreted = start.elapsed().as_secs_f64();
+    let interpreted_registers = processor.registers;
 
-    // Loop 2 (8-17):
-    //
-    // loop {
-        // [3] += [5]&255
-        // [3] = [3]&16777215
-        // [3] *= 65899
-        // [3] = [3]&16777215
-        // if 256>[5] {
-            // break to command 28
-        // }
-        // [2] = 0
-        // Do loop 1
-    // }
-    //
-    // Command 28 offers a chance to exit if [3] == [0],
-    // otherwise [5] = [3]|65536 and [3] = 15028787, then start loop 2
+    let mut processor = new_processor(program);
+    let mut compiled =
+        Compiled::new(&program.commands, program.ip_register, program.register_count)?;
+    let start = Instant::now();
+    for _ in 0..CHECKS {
+        compiled.run(&mut processor, Some(check_index))?;
+    }
+    let fast = start.elapsed().as_secs_f64();
+    assert_eq!(processor.registers, interpreted_registers);
+    assert_eq!(compiled.executed(), steps);
 
-    // Optimised reproduction of the program from when it initially passes the
-    // check that inputs are numbers and not strings:
-    let mut set = HashSet::new();
-    let mut first_value = None;
-    let mut prev_value = None;
+    println!("Ran {} instructions", steps);
+    println!("Interpreter: {:.3}s, {:.0} instructions per second",
+             interpreted, steps as f64 / interpreted);
+    println!("Compiled:    {:.3}s, {:.0} instructions per second",
+             fast, steps as f64 / fast);
+    println!("Speedup:     {:.1}x", interpreted / fast);
+    Ok(())
+}
 
-    //let mut two = 0;
-    let mut three = 15028787;
-    let mut five = 0|65536;
-    loop {
-        loop {
-            three += five&255;
-            three = three&16777215;
-            three *= 65899;
-            three = three&16777215;
-            if 256 > five {
-                break;
-            }
-            //two = 0;
-
-            // We can remove this loop (and variable 2 entirely), see below
-            /*
-            loop {
-                if 256*(two+1) > five {
-                    five = two;
-                    break;
+fn main() -> Result<(), ErrorHolder> {
+    let input = fs::read_to_string("input.txt")?;
+    let program = Program::parse(&input)?;
+
+    // Pass `debug` to step through the program interactively, `cfg` to print
+    // the control flow graph in DOT format, `decompile` to print pseudo-code,
+    // `effects` to print what each block does to the registers or `bench` to
+    // compare the speed of the interpreter and compiled program
+    match env::args().nth(1).as_deref() {
+        Some("debug") => {
+            let processor = new_processor(&program);
+            let mut debugger = Debugger::new(processor, &program.commands);
+            let stdin = io::stdin();
+            debugger.repl(stdin.lock(), io::stdout())?;
+            return Ok(());
+        }
+        Some("cfg") => {
+            print!("{}", ControlFlowGraph::new(&program).to_dot());
+            return Ok(());
+        }
+        Some("decompile") => {
+            print!("{}", ControlFlowGraph::new(&program).decompile());
+            return Ok(());
+        }
+        Some("effects") => {
+            for effect in ControlFlowGraph::new(&program).effects() {
+                println!("{}..={}:", effect.start, effect.end);
+                for line in effect.to_string().lines() {
+                    println!("    {}", line);
                 }
-                two += 1;
             }
-            */
-            five = ((five as f64) / 256.0).floor() as i32;
+            return Ok(());
         }
+        _ => {}
+    }
+
+    let (check_index, check_register) = find_halting_check(&program)
+        .ok_or("Didn't find an eqrr command which reads register [0]")?;
+
+    if env::args().nth(1).as_deref() == Some("bench") {
+        return benchmark(&program, check_index);
+    }
 
-        // This is the point in the loop where we can exit if [0] == [3]
-        //
-        // Part 1. Setting [0] equal to the first value of [3] will allow us
-        // to exit the program the quickest.
-        if first_value == None {
-            first_value = Some(three);
+    // Run the program with [0] = 0 so that it never halts, and record the
+    // value that [0] is compared against every time the check is reached.
+    //
+    // Part 1. Setting [0] equal to the first value compared against will
+    // allow the program to halt the quickest.
+    //
+    // Part 2. The values compared against must eventually repeat, at which
+    // point the program would loop forever. Setting [0] to the last value
+    // before the repetition will run the program for the longest whilst still
+    // halting.
+    //
+    // The program multiplies numbers close to 2^24 by 65899 before masking
+    // them back down, which doesn't fit in an i32 so use i64 registers and
+    // check that nothing overflows them. This takes billions of steps so run
+    // a compiled copy of the program, stopping every time it reaches the check.
+    let mut processor = new_processor(&program);
+    let mut compiled =
+        Compiled::new(&program.commands, program.ip_register, program.register_count)?;
+    let mut seen = HashSet::new();
+    let mut first_value = None;
+    let mut prev_value = None;
+    loop {
+        if compiled.run(&mut processor, Some(check_index))? == Stop::Halted {
+            return Err("The program halted with [0] = 0".into());
+        }
+
+        let value = processor.read(check_register);
+        if first_value.is_none() {
+            first_value = Some(value);
         }
-        // Part 2. Check for repeated [3] values. Assuming that the values
-        // repeat then setting [0] to the last value before repetition will run
-        // the program for the longest whilst still halting.
-        if !set.insert(three) {
+        if !seen.insert(value) {
             break;
         }
-        prev_value = Some(three);
-
-        five = three|65536;
-        three = 15028787;
+        prev_value = Some(value);
     }
 
+    // Check the part 1 answer by running the real program on the device, it
+    // should halt after reaching the comparison for the first time
+    let mut processor = new_processor(&program);
+    processor.write(0, first_value.unwrap());
+    processor.run_program(&program.commands)?;
+
     println!("For the shortest running time set register [0] to {}.",
              first_value.unwrap());
     println!("For the longest running time set register [0] to {}.",
@@ -0,0 +1,364 @@
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+use crate::opcode::Opcode::*;
+use crate::processor::{Command, Processor};
+use crate::program::Program;
+use crate::symbolic::Effect;
+use crate::word::Arithmetic;
+
+// Where control goes after a command has run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    // Carries on from the given command. This is just the next command unless
+    // a constant gets written to the instruction pointer.
+    Jump(i32),
+    // Adds a register to the instruction pointer straight after a comparison
+    // set it to 0 or 1, i.e. skips the next command if the comparison held
+    Branch { register: i32, taken: i32, not_taken: i32 },
+    // Jumps to somewhere which depends on registers we can't know
+    Indirect,
+}
+
+fn writes_ip(command: &Command, ip_register: usize) -> bool {
+    command.c == ip_register as i32
+}
+
+fn command_exit(program: &Program, i: usize) -> Exit {
+    let ip = program.ip_register as i32;
+    let command = &program.commands[i];
+    if !writes_ip(command, program.ip_register) {
+        return Exit::Jump(i as i32 + 1);
+    }
+
+    // If the only register read is the instruction pointer then we know its
+    // value, so can just run the command to find where it jumps to
+    let (a_register, b_register) = command.opcode.register_operands();
+    let a_known = !a_register || command.a == ip;
+    let b_known = !b_register || command.b == ip;
+    if a_known && b_known {
+        let mut p = Processor::<i64>::with_ip_register(program.ip_register + 1,
+                                                      program.ip_register)
+            .with_arithmetic(Arithmetic::Checked);
+        p.jump(i);
+        return match p.run_command(command) {
+            Ok(()) => Exit::Jump(i32::try_from(p.ip() + 1).unwrap_or(i32::MAX)),
+            Err(_) => Exit::Indirect,
+        };
+    }
+
+    // Conditional jumps look like `gtrr 5 2 4` followed by `addr 4 1 1`
+    if command.opcode == Addr && i > 0 {
+        let register = if command.a == ip { command.b } else { command.a };
+        let previous = &program.commands[i - 1];
+        if (command.a == ip || command.b == ip) && previous.opcode.is_comparison() &&
+                previous.c == register && !writes_ip(previous, program.ip_register) {
+            let next = i as i32 + 1;
+            return Exit::Branch { register, taken: next + 1, not_taken: next };
+        }
+    }
+
+    Exit::Indirect
+}
+
+// A straight run of commands which is only ever entered at the top
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub exit: Exit,
+}
+
+pub struct ControlFlowGraph<'a> {
+    program: &'a Program,
+    exits: Vec<Exit>,
+    pub blocks: Vec<BasicBlock>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LoopContext {
+    header: usize,
+    exit: usize,
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    pub fn new(program: &'a Program) -> ControlFlowGraph<'a> {
+        let len = program.commands.len();
+        let exits: Vec<_> = (0..len).map(|i| command_exit(program, i)).collect();
+
+        // Blocks start at the start of the program, at anywhere which is
+        // jumped to and straight after any jump
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        for (i, command) in program.commands.iter().enumerate() {
+            if !writes_ip(command, program.ip_register) {
+                continue;
+            }
+            leaders.insert(i + 1);
+            match exits[i] {
+                Exit::Jump(t) => { leaders.insert(t as usize); }
+                Exit::Branch { taken, not_taken, .. } => {
+                    leaders.insert(taken as usize);
+                    leaders.insert(not_taken as usize);
+                }
+                Exit::Indirect => {}
+            }
+        }
+
+        let starts: Vec<_> = leaders.into_iter().filter(|&l| l < len).collect();
+        let blocks = starts.iter().enumerate().map(|(n, &start)| {
+            let end = starts.get(n + 1).map_or(len, |&next| next) - 1;
+            BasicBlock { start, end, exit: exits[end] }
+        }).collect();
+
+        ControlFlowGraph { program, exits, blocks }
+    }
+
+    // What each block does to the registers, worked out symbolically
+    pub fn effects(&self) -> Vec<Effect> {
+        self.blocks.iter()
+            .map(|b| Effect::of_range(self.program, b.start, b.end)
+                 .expect("Only the last command of a block can jump"))
+            .collect()
+    }
+
+    fn in_program(&self, target: i32) -> bool {
+        target >= 0 && target < self.program.commands.len() as i32
+    }
+
+    fn node(&self, target: i32) -> String {
+        if self.in_program(target) { format!("n{}", target) } else { "halt".to_string() }
+    }
+
+    // The graph in Graphviz DOT format
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph elfcode {\n");
+        dot += "    node [shape=box, fontname=\"monospace\"];\n";
+        dot += "    halt [shape=oval];\n";
+        if self.blocks.iter().any(|b| b.exit == Exit::Indirect) {
+            dot += "    unknown [shape=oval, label=\"?\"];\n";
+        }
+
+        for block in &self.blocks {
+            let label: String = (block.start..=block.end)
+                .map(|i| format!("{}: {}\\l", i, self.program.commands[i]))
+                .collect();
+            dot += &format!("    n{} [label=\"{}\"];\n", block.start, label);
+
+            match block.exit {
+                Exit::Jump(t) => {
+                    dot += &format!("    n{} -> {};\n", block.start, self.node(t));
+                }
+                Exit::Branch { register, taken, not_taken } => {
+                    dot += &format!("    n{} -> {} [label=\"r{}\"];\n",
+                                    block.start, self.node(taken), register);
+                    dot += &format!("    n{} -> {} [label=\"!r{}\"];\n",
+                                    block.start, self.node(not_taken), register);
+                }
+                Exit::Indirect => {
+                    dot += &format!("    n{} -> unknown [style=dashed];\n", block.start);
+                }
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    fn operand(&self, i: usize, value: i32, is_register: bool) -> String {
+        if !is_register {
+            value.to_string()
+        }
+        else if value == self.program.ip_register as i32 {
+            // The instruction pointer always holds the current position
+            i.to_string()
+        }
+        else {
+            format!("r{}", value)
+        }
+    }
+
+    fn expression(&self, i: usize) -> String {
+        let command = &self.program.commands[i];
+        let (a_register, b_register) = command.opcode.register_operands();
+        let a = self.operand(i, command.a, a_register);
+        let b = self.operand(i, command.b, b_register);
+        match command.opcode {
+            Addr | Addi => format!("{} + {}", a, b),
+            Mulr | Muli => format!("{} * {}", a, b),
+            Banr | Bani => format!("{} & {}", a, b),
+            Borr | Bori => format!("{} | {}", a, b),
+            Setr | Seti => a,
+            Gtir | Gtri | Gtrr => format!("{} > {}", a, b),
+            Eqir | Eqri | Eqrr => format!("{} == {}", a, b),
+        }
+    }
+
+    fn assignment(&self, i: usize) -> String {
+        let command = &self.program.commands[i];
+        let (a_register, b_register) = command.opcode.register_operands();
+        let symbol = match command.opcode {
+            Addr | Addi => Some("+"),
+            Mulr | Muli => Some("*"),
+            Banr | Bani => Some("&"),
+            Borr | Bori => Some("|"),
+            _ => None,
+        };
+
+        if let Some(symbol) = symbol {
+            if a_register && command.a == command.c {
+                let b = self.operand(i, command.b, b_register);
+                return format!("r{} {}= {}", command.c, symbol, b);
+            }
+            if b_register && command.b == command.c {
+                let a = self.operand(i, command.a, a_register);
+                return format!("r{} {}= {}", command.c, symbol, a);
+            }
+        }
+        format!("r{} = {}", command.c, self.expression(i))
+    }
+
+    fn jump_statement(&self, target: i32, ctx: Option<LoopContext>) -> String {
+        match ctx {
+            _ if !self.in_program(target) => "halt".to_string(),
+            Some(ctx) if target == ctx.header as i32 => "continue".to_string(),
+            Some(ctx) if target == ctx.exit as i32 => "break".to_string(),
+            _ => format!("goto {}", target),
+        }
+    }
+
+    // The last command in the range which jumps back to the given command
+    fn latch(&self, header: usize, end: usize) -> Option<usize> {
+        (header..=end).rev().find(|&l| {
+            let command = &self.program.commands[l];
+            match self.exits[l] {
+                Exit::Jump(t) => writes_ip(command, self.program.ip_register) &&
+                                 t == header as i32,
+                Exit::Branch { taken, not_taken, .. } =>
+                    taken == header as i32 || not_taken == header as i32,
+                Exit::Indirect => false,
+            }
+        })
+    }
+
+    // A structured listing of the program, with the index of the command
+    // responsible in the left hand column
+    pub fn decompile(&self) -> String {
+        let mut lines = vec![];
+        let len = self.program.commands.len();
+        if len > 0 {
+            self.emit_range(&mut lines, 0, len - 1, None, 0, false);
+        }
+        lines.iter().map(|(i, depth, text): &(Option<usize>, usize, String)| {
+            let index = i.map_or(String::new(), |i| i.to_string());
+            format!("{:>4} | {}{}\n", index, "    ".repeat(*depth), text)
+        }).collect()
+    }
+
+    fn emit_range(&self, lines: &mut Vec<(Option<usize>, usize, String)>, start: usize,
+                  end: usize, ctx: Option<LoopContext>, depth: usize, in_loop: bool) {
+        let mut i = start;
+        while i <= end {
+            // The loop we're already inside starts at the start of the range
+            if !(in_loop && i == start) {
+                if let Some(latch) = self.latch(i, end) {
+                    self.emit_loop(lines, i, latch, depth);
+                    i = latch + 1;
+                    continue;
+                }
+            }
+            i = self.emit_command(lines, i, end, ctx, depth);
+        }
+    }
+
+    fn emit_loop(&self, lines: &mut Vec<(Option<usize>, usize, String)>, header: usize,
+                 latch: usize, depth: usize) {
+        let ctx = Some(LoopContext { header, exit: latch + 1 });
+
+        // Loops which end with a conditional skip over the jump back to the
+        // top are do-while loops
+        let latch_jumps_back = self.exits[latch] == Exit::Jump(header as i32);
+        if latch > header && latch_jumps_back {
+            if let Exit::Branch { register, taken, .. } = self.exits[latch - 1] {
+                if taken == latch as i32 + 1 {
+                    lines.push((None, depth, "do {".to_string()));
+                    if latch >= header + 2 {
+                        self.emit_range(lines, header, latch - 2, ctx, depth + 1, true);
+                    }
+                    lines.push((Some(latch - 1), depth, format!("}} while (!r{})", register)));
+                    return;
+                }
+            }
+        }
+
+        lines.push((None, depth, "loop {".to_string()));
+        self.emit_range(lines, header, latch, ctx, depth + 1, true);
+        lines.push((None, depth, "}".to_string()));
+    }
+
+    // Writes out the command at i, along with any commands it absorbs into a
+    // structured statement. Returns the next command to write out.
+    fn emit_command(&self, lines: &mut Vec<(Option<usize>, usize, String)>, i: usize,
+                    end: usize, ctx: Option<LoopContext>, depth: usize) -> usize {
+        let command = &self.program.commands[i];
+        match self.exits[i] {
+            Exit::Jump(_) if !writes_ip(command, self.program.ip_register) => {
+                lines.push((Some(i), depth, self.assignment(i)));
+            }
+            Exit::Jump(t) if t == i as i32 + 1 => {}
+            Exit::Jump(t) => {
+                // The jump back to the top at the end of a loop is implicit
+                let is_latch = ctx.is_some_and(|c| c.exit == i + 1 && c.header as i32 == t);
+                if !is_latch {
+                    lines.push((Some(i), depth, self.jump_statement(t, ctx)));
+                }
+            }
+            Exit::Branch { register, taken, .. } => {
+                // The usual idiom is a conditional skip over a jump
+                let next = i + 1;
+                let skipped = match self.exits.get(next) {
+                    Some(&Exit::Jump(t)) if next <= end &&
+                        writes_ip(&self.program.commands[next], self.program.ip_register) => Some(t),
+                    _ => None,
+                };
+
+                match skipped {
+                    Some(t) if t == taken => {}
+                    Some(t) if t > taken && t as usize <= end + 1 => {
+                        let mut body = vec![];
+                        self.emit_range(&mut body, taken as usize, t as usize - 1, ctx,
+                                        depth + 1, false);
+
+                        // Keep single jumps on one line
+                        let is_jump = |text: &str| {
+                            ["break", "continue", "halt", "goto "].iter()
+                                .any(|j| text.starts_with(j))
+                        };
+                        if body.len() == 1 && is_jump(&body[0].2) {
+                            lines.push((Some(i), depth, format!("if (r{}) {}", register, body[0].2)));
+                        }
+                        else {
+                            lines.push((Some(i), depth, format!("if (r{}) {{", register)));
+                            lines.append(&mut body);
+                            lines.push((None, depth, "}".to_string()));
+                        }
+                        return t as usize;
+                    }
+                    Some(t) => {
+                        let statement = self.jump_statement(t, ctx);
+                        lines.push((Some(i), depth, format!("if (!r{}) {}", register, statement)));
+                    }
+                    None => {
+                        let statement = self.jump_statement(taken, ctx);
+                        lines.push((Some(i), depth, format!("if (r{}) {}", register, statement)));
+                        return next;
+                    }
+                }
+                return taken as usize;
+            }
+            Exit::Indirect => {
+                lines.push((Some(i), depth, format!("goto ({}) + 1", self.expression(i))));
+            }
+        }
+        i + 1
+    }
+}
@@ -0,0 +1,196 @@
+use crate::opcode::Opcode;
+use crate::processor::{Command, Overflow, Processor};
+use crate::word::{Arithmetic, Word};
+
+// A command with its operands decoded ahead of time. Registers are checked to
+// exist and immediates are converted to words once, so running an operation
+// is a single match with no further validation.
+#[derive(Debug, Clone, Copy)]
+enum Op<W> {
+    Addr(usize, usize),
+    Addi(usize, W),
+    Mulr(usize, usize),
+    Muli(usize, W),
+    Banr(usize, usize),
+    Bani(usize, W),
+    Borr(usize, usize),
+    Bori(usize, W),
+    Setr(usize),
+    Seti(W),
+    Gtir(W, usize),
+    Gtri(usize, W),
+    Gtrr(usize, usize),
+    Eqir(W, usize),
+    Eqri(usize, W),
+    Eqrr(usize, usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Decoded<W> {
+    op: Op<W>,
+    c: usize,
+    // Whether the command writes the instruction pointer, and so might jump
+    jumps: bool,
+}
+
+// Why a command couldn't be compiled
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompileError {
+    pub index: usize,
+    pub command: Command,
+    pub message: String,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Can't compile `{}` at {}: {}", self.command, self.index, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// Why a compiled program stopped running
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Stop {
+    Halted,
+    // The instruction pointer reached the given command, which hasn't run yet
+    Breakpoint(usize),
+}
+
+// A program pre-decoded for fast execution. It runs against the registers of
+// a Processor, so gives exactly the same results as Processor::run_program.
+#[derive(Debug, Clone)]
+pub struct Compiled<W = i32> {
+    commands: Vec<Command>,
+    decoded: Vec<Decoded<W>>,
+    ip_register: usize,
+    register_count: usize,
+    // How many commands have been run so far
+    executed: u64,
+}
+
+impl<W: Word> Compiled<W> {
+    pub fn new(commands: &[Command], ip_register: usize, register_count: usize)
+        -> Result<Compiled<W>, CompileError> {
+        assert!(ip_register < register_count);
+        let decoded = commands.iter().enumerate()
+            .map(|(index, command)| decode(index, command, register_count)
+                 .map(|op| Decoded { op, c: command.c as usize, jumps: command.c as usize == ip_register }))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Compiled {
+            commands: commands.to_vec(),
+            decoded,
+            ip_register,
+            register_count,
+            executed: 0,
+        })
+    }
+
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    // Run the program on the processor until it halts, or until the
+    // instruction pointer reaches the breakpoint. At least one command is
+    // always run, so calling this again carries on past the breakpoint.
+    pub fn run(&mut self, processor: &mut Processor<W>, breakpoint: Option<usize>)
+        -> Result<Stop, Overflow> {
+        assert_eq!(processor.ip_register, Some(self.ip_register));
+        assert_eq!(processor.registers.0.len(), self.register_count);
+        let arithmetic = processor.arithmetic;
+        let registers = &mut processor.registers.0[..];
+        let breakpoint = breakpoint.unwrap_or(usize::MAX);
+        let ip_register = self.ip_register;
+        let program_length = self.decoded.len() as i64;
+
+        let mut ip = registers[ip_register].to_i64().unwrap_or(i64::MAX);
+        let mut executed = 0;
+        let stop = loop {
+            if ip < 0 || ip >= program_length {
+                break Ok(Stop::Halted);
+            }
+            let index = ip as usize;
+            if index == breakpoint && executed > 0 {
+                break Ok(Stop::Breakpoint(index));
+            }
+
+            let decoded = &self.decoded[index];
+            registers[ip_register] = W::from_i64(ip).expect("Instruction pointer doesn't fit in a register");
+            match evaluate(decoded.op, registers, arithmetic) {
+                Some(value) => registers[decoded.c] = value,
+                None => break Err(Overflow { command: self.commands[index], index: Some(ip) }),
+            }
+            executed += 1;
+
+            if decoded.jumps {
+                ip = registers[ip_register].to_i64().unwrap_or(i64::MAX);
+            }
+            ip = ip.saturating_add(1);
+        };
+
+        // Leave the instruction pointer where it would be after run_program
+        if let Some(value) = W::from_i64(ip) {
+            registers[ip_register] = value;
+        }
+        self.executed += executed;
+        stop
+    }
+}
+
+fn decode<W: Word>(index: usize, command: &Command, register_count: usize)
+    -> Result<Op<W>, CompileError> {
+    let error = |message: String| CompileError { index, command: *command, message };
+    let register = |value: i32| {
+        if value >= 0 && (value as usize) < register_count {
+            Ok(value as usize)
+        } else {
+            Err(error(format!("There's no register {}", value)))
+        }
+    };
+    let immediate = |value: i32| W::from_i64(value as i64)
+        .ok_or_else(|| error(format!("{} doesn't fit in a register", value)));
+
+    register(command.c)?;
+    let (a, b) = (command.a, command.b);
+    Ok(match command.opcode {
+        Opcode::Addr => Op::Addr(register(a)?, register(b)?),
+        Opcode::Addi => Op::Addi(register(a)?, immediate(b)?),
+        Opcode::Mulr => Op::Mulr(register(a)?, register(b)?),
+        Opcode::Muli => Op::Muli(register(a)?, immediate(b)?),
+        Opcode::Banr => Op::Banr(register(a)?, register(b)?),
+        Opcode::Bani => Op::Bani(register(a)?, immediate(b)?),
+        Opcode::Borr => Op::Borr(register(a)?, register(b)?),
+        Opcode::Bori => Op::Bori(register(a)?, immediate(b)?),
+        Opcode::Setr => Op::Setr(register(a)?),
+        Opcode::Seti => Op::Seti(immediate(a)?),
+        Opcode::Gtir => Op::Gtir(immediate(a)?, register(b)?),
+        Opcode::Gtri => Op::Gtri(register(a)?, immediate(b)?),
+        Opcode::Gtrr => Op::Gtrr(register(a)?, register(b)?),
+        Opcode::Eqir => Op::Eqir(immediate(a)?, register(b)?),
+        Opcode::Eqri => Op::Eqri(register(a)?, immediate(b)?),
+        Opcode::Eqrr => Op::Eqrr(register(a)?, register(b)?),
+    })
+}
+
+#[inline(always)]
+fn evaluate<W: Word>(op: Op<W>, r: &[W], arithmetic: Arithmetic) -> Option<W> {
+    match op {
+        Op::Addr(a, b) => r[a].add(r[b], arithmetic),
+        Op::Addi(a, b) => r[a].add(b, arithmetic),
+        Op::Mulr(a, b) => r[a].mul(r[b], arithmetic),
+        Op::Muli(a, b) => r[a].mul(b, arithmetic),
+        Op::Banr(a, b) => Some(r[a] & r[b]),
+        Op::Bani(a, b) => Some(r[a] & b),
+        Op::Borr(a, b) => Some(r[a] | r[b]),
+        Op::Bori(a, b) => Some(r[a] | b),
+        Op::Setr(a) => Some(r[a]),
+        Op::Seti(a) => Some(a),
+        Op::Gtir(a, b) => Some(W::from_bool(a > r[b])),
+        Op::Gtri(a, b) => Some(W::from_bool(r[a] > b)),
+        Op::Gtrr(a, b) => Some(W::from_bool(r[a] > r[b])),
+        Op::Eqir(a, b) => Some(W::from_bool(a == r[b])),
+        Op::Eqri(a, b) => Some(W::from_bool(r[a] == b)),
+        Op::Eqrr(a, b) => Some(W::from_bool(r[a] == r[b])),
+    }
+}
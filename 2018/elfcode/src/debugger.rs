@@ -0,0 +1,223 @@
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use crate::processor::{Command, Overflow, Processor};
+use crate::word::Word;
+
+// Why the debugger handed control back
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StopReason<W = i32> {
+    // Execution reached a breakpoint, the instruction there hasn't run yet
+    Breakpoint(usize),
+    // The instruction at ip changed a watched register
+    Watchpoint { ip: usize, register: usize, old: W, new: W },
+    // The command couldn't be run because of checked arithmetic
+    Overflow(Overflow),
+    // Ran the requested number of single steps
+    Stepped,
+    // The instruction pointer left the program
+    Halted,
+}
+
+impl<W: Word> std::fmt::Display for StopReason<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StopReason::Breakpoint(ip) => write!(f, "breakpoint at {}", ip),
+            StopReason::Watchpoint { ip, register, old, new } =>
+                write!(f, "{} changed [{}] from {} to {}", ip, register, old, new),
+            StopReason::Overflow(overflow) => write!(f, "{}", overflow),
+            StopReason::Stepped => write!(f, "stepped"),
+            StopReason::Halted => write!(f, "halted"),
+        }
+    }
+}
+
+// Wraps a processor and program to allow stepping through the program,
+// stopping on breakpoints and register watchpoints, and writing a trace of
+// every instruction run as JSON lines
+pub struct Debugger<'a, W = i32> {
+    pub processor: Processor<W>,
+    commands: &'a [Command],
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    trace: Option<Box<dyn Write + 'a>>,
+    steps: u64,
+}
+
+impl<'a, W: Word> Debugger<'a, W> {
+    pub fn new(processor: Processor<W>, commands: &'a [Command]) -> Debugger<'a, W> {
+        assert!(processor.ip_register.is_some());
+        Debugger {
+            processor,
+            commands,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            trace: None,
+            steps: 0,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.insert(ip);
+    }
+
+    pub fn remove_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.remove(&ip);
+    }
+
+    pub fn add_watchpoint(&mut self, register: usize) {
+        assert!(register < self.processor.registers.0.len());
+        self.watchpoints.insert(register);
+    }
+
+    pub fn remove_watchpoint(&mut self, register: usize) {
+        self.watchpoints.remove(&register);
+    }
+
+    // Write a line describing every instruction run from now on to out
+    pub fn trace_to(&mut self, out: Box<dyn Write + 'a>) {
+        self.trace = Some(out);
+    }
+
+    // How many instructions have been run in total
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.processor.is_halted(self.commands)
+    }
+
+    fn write_trace(&mut self, ip: usize, before: &[W]) -> std::io::Result<()> {
+        if let Some(out) = self.trace.as_mut() {
+            let list = |values: &[W]| {
+                values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+            };
+            writeln!(out,
+                     "{{\"step\":{},\"ip\":{},\"instruction\":\"{}\",\"before\":[{}],\"after\":[{}]}}",
+                     self.steps, ip, self.commands[ip], list(before),
+                     list(&self.processor.registers.0))?;
+        }
+        Ok(())
+    }
+
+    // Run a single instruction, ignoring breakpoints
+    fn single_step(&mut self) -> std::io::Result<Option<StopReason<W>>> {
+        if self.is_halted() {
+            return Ok(Some(StopReason::Halted));
+        }
+
+        let ip = self.processor.ip() as usize;
+        let before = self.processor.registers.0.clone();
+        self.steps += 1;
+        let running = match self.processor.step(self.commands) {
+            Ok(running) => running,
+            Err(overflow) => return Ok(Some(StopReason::Overflow(overflow))),
+        };
+        self.write_trace(ip, &before)?;
+
+        for &register in &self.watchpoints {
+            let (old, new) = (before[register], self.processor.registers.0[register]);
+            if old != new {
+                return Ok(Some(StopReason::Watchpoint { ip, register, old, new }));
+            }
+        }
+        if !running {
+            return Ok(Some(StopReason::Halted));
+        }
+        Ok(None)
+    }
+
+    // Run up to count instructions, stopping early for watchpoints or halting
+    pub fn step(&mut self, count: u64) -> std::io::Result<StopReason<W>> {
+        for _ in 0..count {
+            if let Some(reason) = self.single_step()? {
+                return Ok(reason);
+            }
+        }
+        Ok(StopReason::Stepped)
+    }
+
+    // Run until a breakpoint or watchpoint is hit or the program halts. The
+    // current instruction always runs, so that continuing from a breakpoint
+    // doesn't immediately stop at it again.
+    pub fn resume(&mut self) -> std::io::Result<StopReason<W>> {
+        loop {
+            if let Some(reason) = self.single_step()? {
+                return Ok(reason);
+            }
+            let ip = self.processor.ip() as usize;
+            if self.breakpoints.contains(&ip) {
+                return Ok(StopReason::Breakpoint(ip));
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        if self.is_halted() {
+            return format!("halted {}", self.processor.registers);
+        }
+        let ip = self.processor.ip() as usize;
+        format!("{}: {} {}", ip, self.commands[ip], self.processor.registers)
+    }
+
+    // A simple command line interface to the debugger, reading commands from
+    // input and writing responses to output until told to quit
+    pub fn repl<R: BufRead, O: Write>(&mut self, input: R, mut output: O)
+        -> std::io::Result<()> {
+        writeln!(output, "{}", self.describe())?;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<_> = line.split_whitespace().collect();
+            let arg = |i: usize| words.get(i).and_then(|w| w.parse::<i64>().ok());
+
+            match (words.first().cloned(), arg(1), arg(2)) {
+                (Some("s"), count, _) | (Some("step"), count, _) => {
+                    let reason = self.step(count.unwrap_or(1) as u64)?;
+                    if reason != StopReason::Stepped {
+                        writeln!(output, "Stopped: {}", reason)?;
+                    }
+                }
+                (Some("c"), _, _) | (Some("continue"), _, _) => {
+                    let reason = self.resume()?;
+                    writeln!(output, "Stopped: {}", reason)?;
+                }
+                (Some("b"), Some(ip), _) => {
+                    self.add_breakpoint(ip as usize);
+                    continue;
+                }
+                (Some("db"), Some(ip), _) => {
+                    self.remove_breakpoint(ip as usize);
+                    continue;
+                }
+                (Some("w"), Some(r), _) if (r as usize) < self.processor.registers.0.len() => {
+                    self.add_watchpoint(r as usize);
+                    continue;
+                }
+                (Some("dw"), Some(r), _) => {
+                    self.remove_watchpoint(r as usize);
+                    continue;
+                }
+                (Some("set"), Some(r), Some(v)) if (r as usize) < self.processor.registers.0.len() => {
+                    match W::from_i64(v) {
+                        Some(value) => self.processor.write(r as i32, value),
+                        None => {
+                            writeln!(output, "{} doesn't fit in a register", v)?;
+                            continue;
+                        }
+                    }
+                }
+                (Some("r"), _, _) | (Some("regs"), _, _) => {}
+                (Some("q"), _, _) | (Some("quit"), _, _) => return Ok(()),
+                (None, _, _) => continue,
+                _ => {
+                    writeln!(output, "Commands: s [n], c, b <ip>, db <ip>, w <reg>, dw <reg>, \
+                                      set <reg> <value>, r, q")?;
+                    continue;
+                }
+            }
+            writeln!(output, "{} (after {} steps)", self.describe(), self.steps)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+// The "ElfCode" device shared by days 16, 19 and 21 of Advent of Code 2018
+
+mod analysis;
+mod compile;
+mod debugger;
+mod mapping;
+mod opcode;
+mod optimise;
+mod processor;
+mod program;
+mod symbolic;
+mod word;
+
+pub use crate::analysis::{BasicBlock, ControlFlowGraph, Exit};
+pub use crate::compile::{CompileError, Compiled, Stop};
+pub use crate::debugger::{Debugger, StopReason};
+pub use crate::mapping::{solve, Conflict, Sample, Solution};
+pub use crate::opcode::Opcode;
+pub use crate::optimise::{Optimiser, Shortcut};
+pub use crate::processor::{Command, Overflow, Processor, Registers};
+pub use crate::program::{
+    disassemble, DisassembleError, OpcodeMap, ParseError, Program, RawCommand,
+};
+pub use crate::symbolic::{Effect, Expr};
+pub use crate::word::{Arithmetic, Word};
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::opcode::Opcode;
+use crate::processor::{Processor, Registers};
+use crate::program::{OpcodeMap, ParseError, RawCommand};
+
+// Give up listing the possible mappings after this many
+const MAX_CANDIDATES: usize = 1000;
+
+// One of the samples from day 16 showing the effect of a single command
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Sample {
+    pub before: Registers,
+    pub command: RawCommand,
+    pub after: Registers,
+    // The line of the input the sample starts on
+    pub line: usize,
+}
+
+// Parses lines like `Before: [3, 2, 1, 1]`, allowing any amount of whitespace
+// and any number of registers holding any values
+fn parse_registers(line: &str, label: &str, line_number: usize)
+    -> Result<Registers, ParseError> {
+    let start = line.len() - line.trim_start().len();
+    if !line[start..].starts_with(label) {
+        return Err(ParseError::new(line_number, start + 1,
+                                   format!("Expected '{}'", label)));
+    }
+
+    let open = match line.find('[') {
+        Some(open) => open,
+        None => return Err(ParseError::new(line_number, start + label.len() + 1,
+                                           "Expected '['".to_string())),
+    };
+    let close = match line[open..].find(']') {
+        Some(close) => open + close,
+        None => return Err(ParseError::new(line_number, line.len() + 1,
+                                           "Expected ']'".to_string())),
+    };
+    if let Some(extra) = line[close + 1..].find(|c: char| !c.is_whitespace()) {
+        return Err(ParseError::new(line_number, close + extra + 2,
+                                   "Unexpected text after ']'".to_string()));
+    }
+
+    let mut values = vec![];
+    let mut offset = open + 1;
+    for piece in line[open + 1..close].split(',') {
+        let value = piece.trim();
+        let column = offset + piece.len() - piece.trim_start().len() + 1;
+        values.push(value.parse().map_err(|_| ParseError::new(line_number, column,
+            format!("Failed to parse '{}' as an integer", value)))?);
+        offset += piece.len() + 1;
+    }
+    Ok(Registers(values))
+}
+
+impl Sample {
+    // Parse the three lines of a sample, the first of which is line_number
+    pub fn parse(before: &str, command: &str, after: &str, line_number: usize)
+        -> Result<Sample, ParseError> {
+        let before_registers = parse_registers(before, "Before:", line_number)?;
+        let command = RawCommand::parse(command, line_number + 1)?;
+        let after_registers = parse_registers(after, "After:", line_number + 2)?;
+        if after_registers.0.len() != before_registers.0.len() {
+            return Err(ParseError::new(line_number + 2, 1,
+                format!("Expected {} registers but found {}",
+                        before_registers.0.len(), after_registers.0.len())));
+        }
+
+        Ok(Sample {
+            before: before_registers,
+            command,
+            after: after_registers,
+            line: line_number,
+        })
+    }
+
+    // Whether running the sample's command as the given opcode has the effect
+    // shown. Operands which aren't valid registers never match.
+    pub fn matches(&self, opcode: Opcode) -> bool {
+        let count = self.before.0.len() as i32;
+        let valid = |register: i32| register >= 0 && register < count;
+        let (a_register, b_register) = opcode.register_operands();
+        let c = &self.command;
+        if (a_register && !valid(c.a)) || (b_register && !valid(c.b)) || !valid(c.c) {
+            return false;
+        }
+
+        let mut p = Processor::new(self.before.0.len());
+        p.registers = self.before.clone();
+        p.execute(opcode, c.a, c.b, c.c).is_ok() && p.registers == self.after
+    }
+
+    pub fn matching_opcodes(&self) -> Vec<Opcode> {
+        Opcode::ALL.iter().cloned().filter(|&op| self.matches(op)).collect()
+    }
+}
+
+// Samples which can't all be true at once. They all either use the given
+// opcode number or were needed to rule out the opcodes it could have been.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Conflict {
+    pub opcode: i32,
+    // Indices into the samples given to the solver
+    pub samples: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Solution {
+    Unique(OpcodeMap),
+    // Every mapping consistent with the samples, up to a limit
+    Ambiguous(Vec<OpcodeMap>),
+    Inconsistent(Vec<Conflict>),
+}
+
+// Try to give the number an opcode, moving other numbers on to different
+// opcodes to make room if need be. `owner` is which number has each opcode.
+fn augment(number: i32, candidates: &BTreeMap<i32, BTreeSet<Opcode>>,
+           used: &BTreeSet<Opcode>, owner: &mut BTreeMap<Opcode, i32>,
+           visited: &mut BTreeSet<Opcode>) -> bool {
+    for &opcode in &candidates[&number] {
+        if used.contains(&opcode) || !visited.insert(opcode) {
+            continue;
+        }
+        let free = match owner.get(&opcode) {
+            Some(&other) => augment(other, candidates, used, owner, visited),
+            None => true,
+        };
+        if free {
+            owner.insert(opcode, number);
+            return true;
+        }
+    }
+    false
+}
+
+// Whether every number can be given a different opcode from its candidates,
+// leaving out the ones already used. Counting candidates alone can miss that
+// there's no way to do it, e.g. three numbers which could only be one of the
+// same two opcodes.
+fn can_complete(numbers: &[i32], candidates: &BTreeMap<i32, BTreeSet<Opcode>>,
+                used: &BTreeSet<Opcode>) -> bool {
+    let mut owner = BTreeMap::new();
+    numbers.iter().all(|&n| augment(n, candidates, used, &mut owner, &mut BTreeSet::new()))
+}
+
+// Fill in the rest of a mapping in every possible way. Branches which can't be
+// completed are cut off straight away, so the search only ever follows ones
+// which lead to a mapping and stops soon after finding the most we list.
+fn enumerate(numbers: &[i32], candidates: &BTreeMap<i32, BTreeSet<Opcode>>,
+             partial: &mut OpcodeMap, used: &mut BTreeSet<Opcode>,
+             found: &mut Vec<OpcodeMap>) {
+    if found.len() >= MAX_CANDIDATES || !can_complete(numbers, candidates, used) {
+        return;
+    }
+    let (number, rest) = match numbers.split_first() {
+        Some(split) => split,
+        None => {
+            found.push(partial.clone());
+            return;
+        }
+    };
+
+    for &opcode in &candidates[number] {
+        if used.insert(opcode) {
+            partial.insert(*number, opcode);
+            enumerate(rest, candidates, partial, used, found);
+            partial.remove(number);
+            used.remove(&opcode);
+        }
+    }
+}
+
+// Work out which opcode each number refers to from the samples
+pub fn solve(samples: &[Sample]) -> Solution {
+    let mut candidates: BTreeMap<i32, BTreeSet<Opcode>> = BTreeMap::new();
+    // The samples responsible for narrowing down each number's candidates
+    let mut reasons: BTreeMap<i32, BTreeSet<usize>> = BTreeMap::new();
+    let mut conflicts = vec![];
+
+    for (i, sample) in samples.iter().enumerate() {
+        let number = sample.command.opcode;
+        let matching: BTreeSet<_> = sample.matching_opcodes().into_iter().collect();
+        let current = candidates.entry(number)
+            .or_insert_with(|| Opcode::ALL.iter().cloned().collect());
+        let narrowed: BTreeSet<_> = current.intersection(&matching).cloned().collect();
+        if narrowed.len() == current.len() {
+            continue;
+        }
+
+        let reason = reasons.entry(number).or_default();
+        reason.insert(i);
+        if narrowed.is_empty() && !current.is_empty() {
+            conflicts.push(Conflict { opcode: number, samples: reason.iter().cloned().collect() });
+        }
+        *current = narrowed;
+    }
+    if !conflicts.is_empty() {
+        return Solution::Inconsistent(conflicts);
+    }
+
+    // Any number with only one possibility rules that opcode out for every
+    // other number, keep going until nothing changes
+    let mut resolved = BTreeSet::new();
+    loop {
+        let next = candidates.iter()
+            .find(|(n, ops)| ops.len() == 1 && !resolved.contains(*n))
+            .map(|(&n, ops)| (n, *ops.iter().next().unwrap()));
+        let (number, opcode) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        resolved.insert(number);
+
+        let cause = reasons.get(&number).cloned().unwrap_or_default();
+        for (&other, ops) in candidates.iter_mut() {
+            if other != number && ops.remove(&opcode) {
+                let reason = reasons.entry(other).or_default();
+                reason.extend(cause.iter().cloned());
+                if ops.is_empty() {
+                    conflicts.push(Conflict { opcode: other, samples: reason.iter().cloned().collect() });
+                }
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        return Solution::Inconsistent(conflicts);
+    }
+
+    let numbers: Vec<_> = candidates.keys().cloned().collect();
+    let mut found = vec![];
+    enumerate(&numbers, &candidates, &mut OpcodeMap::new(), &mut BTreeSet::new(), &mut found);
+    match found.len() {
+        0 => {
+            // No way of giving every number a different opcode, so blame all
+            // the samples for the numbers which are still undecided
+            let conflicts = numbers.iter()
+                .filter(|n| candidates[n].len() > 1)
+                .map(|&n| Conflict {
+                    opcode: n,
+                    samples: reasons.get(&n).map_or(vec![], |r| r.iter().cloned().collect()),
+                })
+                .collect();
+            Solution::Inconsistent(conflicts)
+        }
+        1 => Solution::Unique(found.pop().unwrap()),
+        _ => Solution::Ambiguous(found),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impossible_mappings_are_spotted_without_searching_them_all() {
+        // Sixteen numbers which could be anything, apart from the last three
+        // which all have to be one of the same two opcodes
+        let mut candidates = BTreeMap::new();
+        for n in 0..16 {
+            let ops: BTreeSet<_> = if n < 13 {
+                Opcode::ALL.iter().cloned().collect()
+            }
+            else {
+                vec![Opcode::Addr, Opcode::Addi].into_iter().collect()
+            };
+            candidates.insert(n, ops);
+        }
+        let numbers: Vec<_> = candidates.keys().cloned().collect();
+        assert!(!can_complete(&numbers, &candidates, &BTreeSet::new()));
+
+        let mut found = vec![];
+        enumerate(&numbers, &candidates, &mut OpcodeMap::new(), &mut BTreeSet::new(), &mut found);
+        assert!(found.is_empty());
+
+        // Giving one of them more room makes it possible again
+        candidates.get_mut(&15).unwrap().insert(Opcode::Seti);
+        assert!(can_complete(&numbers, &candidates, &BTreeSet::new()));
+        enumerate(&numbers, &candidates, &mut OpcodeMap::new(), &mut BTreeSet::new(), &mut found);
+        assert_eq!(found.len(), MAX_CANDIDATES);
+    }
+}
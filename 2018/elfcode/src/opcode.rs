@@ -0,0 +1,76 @@
+// The sixteen instructions understood by the device from days 16, 19 and 21
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub enum Opcode {
+    Addr,
+    Addi,
+    Mulr,
+    Muli,
+    Banr,
+    Bani,
+    Borr,
+    Bori,
+    Setr,
+    Seti,
+    Gtir,
+    Gtri,
+    Gtrr,
+    Eqir,
+    Eqri,
+    Eqrr,
+}
+use self::Opcode::*;
+
+impl Opcode {
+    pub const ALL: [Opcode; 16] = [
+        Addr, Addi, Mulr, Muli, Banr, Bani, Borr, Bori,
+        Setr, Seti, Gtir, Gtri, Gtrr, Eqir, Eqri, Eqrr,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Addr => "addr",
+            Addi => "addi",
+            Mulr => "mulr",
+            Muli => "muli",
+            Banr => "banr",
+            Bani => "bani",
+            Borr => "borr",
+            Bori => "bori",
+            Setr => "setr",
+            Seti => "seti",
+            Gtir => "gtir",
+            Gtri => "gtri",
+            Gtrr => "gtrr",
+            Eqir => "eqir",
+            Eqri => "eqri",
+            Eqrr => "eqrr",
+        }
+    }
+
+    // Whether the a and b operands refer to registers, rather than being
+    // immediate values or unused
+    pub fn register_operands(self) -> (bool, bool) {
+        match self {
+            Addr | Mulr | Banr | Borr | Gtrr | Eqrr => (true, true),
+            Addi | Muli | Bani | Bori | Gtri | Eqri => (true, false),
+            Gtir | Eqir => (false, true),
+            Setr => (true, false),
+            Seti => (false, false),
+        }
+    }
+
+    // Whether the result is always either 0 or 1
+    pub fn is_comparison(self) -> bool {
+        matches!(self, Gtir | Gtri | Gtrr | Eqir | Eqri | Eqrr)
+    }
+
+    pub fn from_name(name: &str) -> Option<Opcode> {
+        Opcode::ALL.iter().cloned().find(|op| op.name() == name)
+    }
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+
+use crate::opcode::Opcode;
+use crate::opcode::Opcode::*;
+use crate::processor::{Command, Overflow, Processor};
+use crate::word::{Arithmetic, Word};
+
+// How many times an instruction must run before we look for a shortcut
+const HOT_THRESHOLD: u64 = 100;
+
+// The registers a loop idiom works with. These get bound to real register
+// numbers when a template is matched against a program.
+const I: usize = 0; // outer loop counter
+const J: usize = 1; // inner loop counter
+const T: usize = 2; // temporary used for tests
+const N: usize = 3; // the number whose divisors are summed
+const S: usize = 4; // the running sum
+const NUM_VARS: usize = 5;
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Reg(usize),
+    Ip,
+    Imm(i32),
+    // An immediate jump target, relative to the start of the template
+    Target(i32),
+    Any,
+}
+use self::Operand::*;
+
+type Template = [(Opcode, Operand, Operand, Operand)];
+
+// for j in J..=N { if I * j == N { S += I } }
+const INNER_LOOP: [(Opcode, Operand, Operand, Operand); 9] = [
+    (Mulr, Reg(I), Reg(J), Reg(T)),
+    (Eqrr, Reg(T), Reg(N), Reg(T)),
+    (Addr, Reg(T), Ip, Ip),
+    (Addi, Ip, Imm(1), Ip),
+    (Addr, Reg(I), Reg(S), Reg(S)),
+    (Addi, Reg(J), Imm(1), Reg(J)),
+    (Gtrr, Reg(J), Reg(N), Reg(T)),
+    (Addr, Ip, Reg(T), Ip),
+    (Seti, Target(-1), Any, Ip),
+];
+
+// for i in I..=N { for j in 1..=N { if i * j == N { S += i } } }
+const OUTER_LOOP: [(Opcode, Operand, Operand, Operand); 14] = [
+    (Seti, Imm(1), Any, Reg(J)),
+    (Mulr, Reg(I), Reg(J), Reg(T)),
+    (Eqrr, Reg(T), Reg(N), Reg(T)),
+    (Addr, Reg(T), Ip, Ip),
+    (Addi, Ip, Imm(1), Ip),
+    (Addr, Reg(I), Reg(S), Reg(S)),
+    (Addi, Reg(J), Imm(1), Reg(J)),
+    (Gtrr, Reg(J), Reg(N), Reg(T)),
+    (Addr, Ip, Reg(T), Ip),
+    (Seti, Target(0), Any, Ip),
+    (Addi, Reg(I), Imm(1), Reg(I)),
+    (Gtrr, Reg(I), Reg(N), Reg(T)),
+    (Addr, Reg(T), Ip, Ip),
+    (Seti, Target(-1), Any, Ip),
+];
+
+fn is_commutative(opcode: Opcode) -> bool {
+    matches!(opcode, Addr | Mulr | Banr | Borr | Eqrr)
+}
+
+struct Matcher {
+    start: usize,
+    ip_register: i32,
+    vars: [Option<i32>; NUM_VARS],
+}
+
+impl Matcher {
+    fn bind(&mut self, operand: Operand, value: i32) -> bool {
+        match operand {
+            Reg(var) => {
+                if value == self.ip_register {
+                    return false;
+                }
+                match self.vars[var] {
+                    Some(bound) => bound == value,
+                    None => {
+                        // Each variable must be a different register
+                        if self.vars.contains(&Some(value)) {
+                            return false;
+                        }
+                        self.vars[var] = Some(value);
+                        true
+                    }
+                }
+            }
+            Ip => value == self.ip_register,
+            Imm(imm) => value == imm,
+            Target(offset) => value == self.start as i32 + offset,
+            Any => true,
+        }
+    }
+
+    // Try to match the remaining commands against the remaining patterns,
+    // backtracking over the operand order of commutative operations
+    fn matches(&mut self, commands: &[Command], template: &Template) -> bool {
+        let (command, pattern) = match (commands.first(), template.first()) {
+            (Some(command), Some(pattern)) => (command, pattern),
+            _ => return true,
+        };
+        let &(opcode, a, b, c) = pattern;
+        if command.opcode != opcode {
+            return false;
+        }
+
+        let mut orders = vec![(command.a, command.b)];
+        if is_commutative(opcode) {
+            orders.push((command.b, command.a));
+        }
+        let saved = self.vars;
+        for (first, second) in orders {
+            if self.bind(a, first) && self.bind(b, second) && self.bind(c, command.c) &&
+                    self.matches(&commands[1..], &template[1..]) {
+                return true;
+            }
+            self.vars = saved;
+        }
+        false
+    }
+}
+
+// Match a template against the program at the given index, returning the
+// register bound to each variable
+fn match_template(commands: &[Command], ip_register: usize, start: usize,
+                  template: &Template) -> Option<[i32; NUM_VARS]> {
+    let window = commands.get(start..start + template.len())?;
+    let mut matcher = Matcher { start, ip_register: ip_register as i32, vars: [None; NUM_VARS] };
+    if !matcher.matches(window, template) {
+        return None;
+    }
+
+    let mut vars = [0; NUM_VARS];
+    for (var, bound) in vars.iter_mut().zip(matcher.vars.iter()) {
+        *var = (*bound)?;
+    }
+    Some(vars)
+}
+
+// The divisors of n which are at least as big as from, smallest first
+fn divisors_from(n: i64, from: i64) -> Vec<i64> {
+    let mut small = vec![];
+    let mut large = vec![];
+    let mut d = 1;
+    while d * d <= n {
+        if n % d == 0 {
+            let other = n / d;
+            if d >= from {
+                small.push(d);
+            }
+            if other != d && other >= from {
+                large.push(other);
+            }
+        }
+        d += 1;
+    }
+    small.extend(large.iter().rev());
+    small
+}
+
+// Why a shortcut couldn't be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NotApplied {
+    // The registers are outside the range where the shortcut is known to be
+    // equivalent to the loop, so it's left to the interpreter
+    OutOfRange,
+    // Running the loop would overflow a register with checked arithmetic
+    Overflow,
+}
+
+// A recognised loop which can be replaced by a native computation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shortcut {
+    // Adds [i] to [s] if [i] divides [n], leaving [j] past [n]
+    DivisorTest { i: i32, j: i32, t: i32, n: i32, s: i32, exit: usize },
+    // Adds every divisor of [n] from [i] upwards to [s]
+    DivisorSum { i: i32, j: i32, t: i32, n: i32, s: i32, exit: usize },
+}
+
+impl Shortcut {
+    fn find(commands: &[Command], ip_register: usize, start: usize) -> Option<Shortcut> {
+        if let Some(v) = match_template(commands, ip_register, start, &OUTER_LOOP) {
+            let exit = start + OUTER_LOOP.len();
+            return Some(Shortcut::DivisorSum { i: v[I], j: v[J], t: v[T], n: v[N], s: v[S], exit });
+        }
+        if let Some(v) = match_template(commands, ip_register, start, &INNER_LOOP) {
+            let exit = start + INNER_LOOP.len();
+            return Some(Shortcut::DivisorTest { i: v[I], j: v[J], t: v[T], n: v[N], s: v[S], exit });
+        }
+        None
+    }
+
+    // Apply the effect of running the loop to completion. Nothing is changed
+    // if the shortcut can't be used.
+    fn apply<W: Word>(&self, p: &mut Processor<W>) -> Result<(), NotApplied> {
+        // With checked arithmetic a value too big for a register means the
+        // loop would overflow. Otherwise what the loop does with it depends on
+        // how it wraps or saturates, for instance a wrapped product can look
+        // like a divisor, so leave that to the interpreter.
+        let too_big = if p.arithmetic == Arithmetic::Checked {
+            NotApplied::Overflow
+        }
+        else {
+            NotApplied::OutOfRange
+        };
+        let read = |r: i32| p.read(r).to_i64().ok_or(NotApplied::OutOfRange);
+        let fit = |value: Option<i64>| value.and_then(W::from_i64).ok_or(too_big);
+        // Add the divisors one at a time, just like the loop, so the sum
+        // overflows, wraps or saturates the same way
+        let add_up = |start: W, divisors: &[i64]| divisors.iter()
+            .try_fold(start, |sum, &d| sum.add(W::from_i64(d)?, p.arithmetic))
+            .ok_or(NotApplied::Overflow);
+
+        match *self {
+            Shortcut::DivisorTest { i, j, t, n, s, exit } => {
+                let (iv, jv, nv) = (read(i)?, read(j)?, read(n)?);
+                if iv < 1 || jv < 1 || nv < 1 {
+                    return Err(NotApplied::OutOfRange);
+                }
+                let last = std::cmp::max(jv, nv);
+                // The loop multiplies i by every j up to the last
+                fit(iv.checked_mul(last))?;
+                let divides = nv % iv == 0 && nv / iv >= jv && nv / iv <= last;
+                let divisors: &[i64] = if divides { &[iv] } else { &[] };
+                let sum = add_up(p.read(s), divisors)?;
+                let j_value = fit(last.checked_add(1))?;
+
+                p.write(s, sum);
+                p.write(j, j_value);
+                p.write(t, W::ONE);
+                p.jump(exit);
+            }
+            Shortcut::DivisorSum { i, j, t, n, s, exit } => {
+                let (iv, nv) = (read(i)?, read(n)?);
+                if iv < 1 || nv < 1 {
+                    return Err(NotApplied::OutOfRange);
+                }
+                let last = std::cmp::max(iv, nv);
+                // The loop multiplies every i by every j up to n
+                fit(last.checked_mul(nv))?;
+                let sum = add_up(p.read(s), &divisors_from(nv, iv))?;
+                let i_value = fit(last.checked_add(1))?;
+                let j_value = fit(nv.checked_add(1))?;
+
+                p.write(s, sum);
+                p.write(i, i_value);
+                p.write(j, j_value);
+                p.write(t, W::ONE);
+                p.jump(exit);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Shortcut::DivisorTest { n, .. } =>
+                write!(f, "test for a divisor of [{}]", n),
+            Shortcut::DivisorSum { n, .. } =>
+                write!(f, "sum of divisors of [{}]", n),
+        }
+    }
+}
+
+// Runs programs while profiling which instructions are hot. Hot instructions
+// are checked for loops we know how to short-circuit, anything unrecognised
+// is simply interpreted.
+#[derive(Debug, Default)]
+pub struct Optimiser {
+    counts: Vec<u64>,
+    shortcuts: HashMap<usize, Option<Shortcut>>,
+}
+
+impl Optimiser {
+    pub fn new() -> Optimiser {
+        Optimiser::default()
+    }
+
+    // The shortcuts found so far, along with the instruction they start at
+    pub fn shortcuts(&self) -> Vec<(usize, Shortcut)> {
+        let mut found: Vec<_> = self.shortcuts.iter()
+            .filter_map(|(&ip, s)| s.map(|s| (ip, s)))
+            .collect();
+        found.sort_by_key(|&(ip, _)| ip);
+        found
+    }
+
+    pub fn run_program<W: Word>(&mut self, p: &mut Processor<W>, commands: &[Command])
+        -> Result<(), Overflow> {
+        let ip_register = p.ip_register.expect("No instruction pointer");
+        self.counts.resize(commands.len(), 0);
+
+        while !p.is_halted(commands) {
+            let ip = p.ip() as usize;
+
+            self.counts[ip] += 1;
+            if self.counts[ip] == HOT_THRESHOLD {
+                self.shortcuts.insert(ip, Shortcut::find(commands, ip_register, ip));
+            }
+
+            if let Some(Some(shortcut)) = self.shortcuts.get(&ip) {
+                match shortcut.apply(p) {
+                    Ok(()) => continue,
+                    Err(NotApplied::OutOfRange) => {}
+                    Err(NotApplied::Overflow) =>
+                        return Err(Overflow { command: commands[ip], index: Some(ip as i64) }),
+                }
+            }
+
+            p.step(commands)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+
+    // Sums the divisors of 360 into register 0 with the same nested loops as
+    // day 19, but with the variables in different registers
+    const DIVISOR_SUM: &str = "#ip 5
+seti 360 0 4
+seti 1 0 1
+seti 1 0 2
+mulr 1 2 3
+eqrr 3 4 3
+addr 3 5 5
+addi 5 1 5
+addr 1 0 0
+addi 2 1 2
+gtrr 2 4 3
+addr 5 3 5
+seti 2 0 5
+addi 1 1 1
+gtrr 1 4 3
+addr 3 5 5
+seti 1 0 5
+";
+
+    #[test]
+    fn shortcuts_match_the_interpreter() {
+        let program = Program::parse(DIVISOR_SUM).unwrap();
+        let new_processor = || -> Processor {
+            Processor::with_ip_register(program.register_count, program.ip_register)
+        };
+
+        let mut interpreted = new_processor();
+        interpreted.run_program(&program.commands).unwrap();
+        assert_eq!(interpreted.read(0), 1170);
+
+        let mut optimiser = Optimiser::new();
+        let mut optimised = new_processor();
+        optimiser.run_program(&mut optimised, &program.commands).unwrap();
+        assert!(!optimiser.shortcuts().is_empty());
+        assert_eq!(optimised.registers, interpreted.registers);
+    }
+
+    #[test]
+    fn shortcuts_report_overflow() {
+        // Multiplying 50000 by itself doesn't fit in an i32
+        let program = Program::parse(&DIVISOR_SUM.replace("360", "50000")).unwrap();
+
+        let mut wide = Processor::<i64>::with_ip_register(program.register_count,
+                                                          program.ip_register)
+            .with_arithmetic(Arithmetic::Checked);
+        Optimiser::new().run_program(&mut wide, &program.commands).unwrap();
+        assert_eq!(wide.read(0), 121086);
+
+        let mut narrow = Processor::<i32>::with_ip_register(program.register_count,
+                                                            program.ip_register)
+            .with_arithmetic(Arithmetic::Checked);
+        let overflow = Optimiser::new().run_program(&mut narrow, &program.commands).unwrap_err();
+        assert_eq!(overflow.index, Some(2));
+        assert_eq!(overflow.command, program.commands[2]);
+    }
+
+    #[test]
+    fn wrapped_products_are_left_to_the_interpreter() {
+        // A wrapped i*j can equal n without i dividing it, so the shortcut
+        // can't say what the loop does
+        let program = Program::parse(&DIVISOR_SUM.replace("360", "50000")).unwrap();
+        let shortcut = Shortcut::find(&program.commands, 5, 2).unwrap();
+
+        let mut p = Processor::<i32>::with_ip_register(program.register_count,
+                                                       program.ip_register);
+        p.write(4, 50000);
+        p.write(1, 1);
+        p.jump(2);
+        let before = p.registers.clone();
+        assert_eq!(shortcut.apply(&mut p), Err(NotApplied::OutOfRange));
+        assert_eq!(p.registers, before);
+    }
+}
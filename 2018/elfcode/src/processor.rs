@@ -0,0 +1,305 @@
+use crate::opcode::Opcode;
+use crate::word::{Arithmetic, Word};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Registers<W = i32>(pub Vec<W>);
+
+impl<W: Word> Registers<W> {
+    pub fn new(count: usize) -> Registers<W> {
+        Registers(vec![W::ZERO; count])
+    }
+}
+
+impl<W: Word> std::fmt::Display for Registers<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let values: Vec<_> = self.0.iter().map(|v| v.to_string()).collect();
+        write!(f, "[{}]", values.join(", "))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Command {
+    pub opcode: Opcode,
+    pub a: i32,
+    pub b: i32,
+    pub c: i32,
+}
+
+// A command whose result didn't fit in a register, or which used an
+// immediate value that can't be stored in one, when using checked arithmetic
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Overflow {
+    pub command: Command,
+    // Where the command is in the program, if there's an instruction pointer
+    pub index: Option<i64>,
+}
+
+impl std::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "Overflow running `{}` at {}", self.command, index),
+            None => write!(f, "Overflow running `{}`", self.command),
+        }
+    }
+}
+
+impl std::error::Error for Overflow {}
+
+#[derive(Debug, Clone)]
+pub struct Processor<W = i32> {
+    pub registers: Registers<W>,
+    pub ip_register: Option<usize>,
+    pub arithmetic: Arithmetic,
+}
+
+impl<W: Word> std::fmt::Display for Processor<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.registers)
+    }
+}
+
+// Macros for simple binary operations like add
+macro_rules! binaryr {
+    ($name:ident, $op:ident) => {
+        fn $name(&self, a: i32, b: i32) -> Option<W> {
+            self.$op(self.read(a), self.read(b))
+        }
+    }
+}
+macro_rules! binaryi {
+    ($name:ident, $op:ident) => {
+        fn $name(&self, a: i32, b: i32) -> Option<W> {
+            self.$op(self.read(a), W::from_i64(b as i64)?)
+        }
+    }
+}
+
+// Macros for testing functions like equality testing
+macro_rules! testingir {
+    ($name:ident, $op:tt) => {
+        fn $name(&self, a: i32, b: i32) -> Option<W> {
+            Some(W::from_bool(W::from_i64(a as i64)? $op self.read(b)))
+        }
+    }
+}
+macro_rules! testingri {
+    ($name:ident, $op:tt) => {
+        fn $name(&self, a: i32, b: i32) -> Option<W> {
+            Some(W::from_bool(self.read(a) $op W::from_i64(b as i64)?))
+        }
+    }
+}
+macro_rules! testingrr {
+    ($name:ident, $op:tt) => {
+        fn $name(&self, a: i32, b: i32) -> Option<W> {
+            Some(W::from_bool(self.read(a) $op self.read(b)))
+        }
+    }
+}
+
+impl<W: Word> Processor<W> {
+    // A processor without an instruction pointer, as used in day 16
+    pub fn new(register_count: usize) -> Processor<W> {
+        Processor {
+            registers: Registers::new(register_count),
+            ip_register: None,
+            arithmetic: Arithmetic::default(),
+        }
+    }
+
+    // A processor with one of its registers bound to the instruction pointer
+    pub fn with_ip_register(register_count: usize, ip_register: usize)
+        -> Processor<W> {
+        assert!(ip_register < register_count);
+        Processor {
+            registers: Registers::new(register_count),
+            ip_register: Some(ip_register),
+            arithmetic: Arithmetic::default(),
+        }
+    }
+
+    pub fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Processor<W> {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    pub fn read(&self, register: i32) -> W {
+        self.registers.0[register as usize]
+    }
+
+    pub fn write(&mut self, register: i32, value: W) {
+        self.registers.0[register as usize] = value;
+    }
+
+    fn add(&self, x: W, y: W) -> Option<W> {
+        x.add(y, self.arithmetic)
+    }
+
+    fn mul(&self, x: W, y: W) -> Option<W> {
+        x.mul(y, self.arithmetic)
+    }
+
+    fn and(&self, x: W, y: W) -> Option<W> {
+        Some(x & y)
+    }
+
+    fn or(&self, x: W, y: W) -> Option<W> {
+        Some(x | y)
+    }
+
+    binaryr!(addr, add);
+    binaryi!(addi, add);
+
+    binaryr!(mulr, mul);
+    binaryi!(muli, mul);
+
+    binaryr!(banr, and);
+    binaryi!(bani, and);
+
+    binaryr!(borr, or);
+    binaryi!(bori, or);
+
+    fn setr(&self, a: i32, _: i32) -> Option<W> {
+        Some(self.read(a))
+    }
+    fn seti(&self, a: i32, _: i32) -> Option<W> {
+        W::from_i64(a as i64)
+    }
+
+    testingir!(gtir, >);
+    testingri!(gtri, >);
+    testingrr!(gtrr, >);
+
+    testingir!(eqir, ==);
+    testingri!(eqri, ==);
+    testingrr!(eqrr, ==);
+
+    pub fn execute(&mut self, opcode: Opcode, a: i32, b: i32, c: i32)
+        -> Result<(), Overflow> {
+        let result = match opcode {
+            Opcode::Addr => self.addr(a, b),
+            Opcode::Addi => self.addi(a, b),
+            Opcode::Mulr => self.mulr(a, b),
+            Opcode::Muli => self.muli(a, b),
+            Opcode::Banr => self.banr(a, b),
+            Opcode::Bani => self.bani(a, b),
+            Opcode::Borr => self.borr(a, b),
+            Opcode::Bori => self.bori(a, b),
+            Opcode::Setr => self.setr(a, b),
+            Opcode::Seti => self.seti(a, b),
+            Opcode::Gtir => self.gtir(a, b),
+            Opcode::Gtri => self.gtri(a, b),
+            Opcode::Gtrr => self.gtrr(a, b),
+            Opcode::Eqir => self.eqir(a, b),
+            Opcode::Eqri => self.eqri(a, b),
+            Opcode::Eqrr => self.eqrr(a, b),
+        };
+
+        match result {
+            Some(value) => {
+                self.write(c, value);
+                Ok(())
+            }
+            None => Err(Overflow {
+                command: Command { opcode, a, b, c },
+                index: self.ip_register.map(|_| self.ip()),
+            }),
+        }
+    }
+
+    pub fn run_command(&mut self, command: &Command) -> Result<(), Overflow> {
+        self.execute(command.opcode, command.a, command.b, command.c)
+    }
+
+    // The instruction pointer. Values too big to be an index into any
+    // program come out as i64::MAX.
+    pub fn ip(&self) -> i64 {
+        let ip_register = self.ip_register.expect("No instruction pointer");
+        self.read(ip_register as i32).to_i64().unwrap_or(i64::MAX)
+    }
+
+    fn set_ip(&mut self, value: i64) {
+        let ip_register = self.ip_register.expect("No instruction pointer");
+        let value = W::from_i64(value).expect("Instruction pointer doesn't fit in a register");
+        self.write(ip_register as i32, value);
+    }
+
+    // Carry on execution from the given command
+    pub fn jump(&mut self, target: usize) {
+        self.set_ip(target as i64);
+    }
+
+    // Whether the instruction pointer is outside the program, so that it has
+    // halted
+    pub fn is_halted(&self, commands: &[Command]) -> bool {
+        let ip = self.ip();
+        ip < 0 || ip >= commands.len() as i64
+    }
+
+    // Run a single command, returning false if the instruction pointer has
+    // left the program and so the program has halted
+    pub fn step(&mut self, commands: &[Command]) -> Result<bool, Overflow> {
+        // The instruction pointer can start outside the program, or there
+        // might not be a program at all
+        if self.is_halted(commands) {
+            return Ok(false);
+        }
+
+        // Run the command
+        self.run_command(&commands[self.ip() as usize])?;
+
+        // Increment the instruction pointer. If it's too big to go any higher
+        // it's already past the end of the program, so leave it there
+        let ip_register = self.ip_register.expect("No instruction pointer");
+        if let Some(next) = W::from_i64(self.ip().saturating_add(1)) {
+            self.write(ip_register as i32, next);
+        }
+
+        // If the instruction pointer is now outside the program then end
+        Ok(!self.is_halted(commands))
+    }
+
+    pub fn run_program(&mut self, commands: &[Command]) -> Result<(), Overflow> {
+        while self.step(commands)? {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::{Compiled, Stop};
+    use crate::program::Program;
+
+    #[test]
+    fn halts_when_the_instruction_pointer_cannot_go_higher() {
+        let program = Program::parse("#ip 0\nseti 2147483647 0 0\n").unwrap();
+        let new_processor = || -> Processor {
+            Processor::with_ip_register(program.register_count, program.ip_register)
+        };
+
+        let mut interpreted = new_processor();
+        interpreted.run_program(&program.commands).unwrap();
+
+        let mut compiled = new_processor();
+        let stop = Compiled::new(&program.commands, program.ip_register, program.register_count)
+            .unwrap().run(&mut compiled, None).unwrap();
+        assert_eq!(stop, Stop::Halted);
+        assert_eq!(interpreted.registers, compiled.registers);
+        assert_eq!(interpreted.read(0), i32::MAX);
+    }
+    #[test]
+    fn programs_which_start_halted_do_nothing() {
+        let empty = Program::parse("#ip 0\n").unwrap();
+        let mut p: Processor = Processor::with_ip_register(empty.register_count, empty.ip_register);
+        p.run_program(&empty.commands).unwrap();
+        assert_eq!(p.step(&empty.commands), Ok(false));
+
+        let program = Program::parse("#ip 0\nseti 5 0 1\n").unwrap();
+        let mut p: Processor = Processor::with_ip_register(program.register_count,
+                                                           program.ip_register);
+        p.write(0, 3);
+        p.run_program(&program.commands).unwrap();
+        assert_eq!(p.read(1), 0);
+    }
+}
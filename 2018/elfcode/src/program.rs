@@ -0,0 +1,291 @@
+use crate::opcode::Opcode;
+use crate::processor::Command;
+
+// A program written in the textual form used by days 19 and 21, i.e. an
+// `#ip N` declaration followed by one mnemonic command per line
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Program {
+    pub ip_register: usize,
+    // How many registers the program is written for. Every register it uses,
+    // including the instruction pointer, is below this.
+    pub register_count: usize,
+    pub commands: Vec<Command>,
+}
+
+// Where and why parsing failed. Lines and columns both count from 1
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(line: usize, column: usize, message: String) -> ParseError {
+        ParseError { line, column, message }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// A whitespace separated word from a line, along with the column it starts at
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+fn tokenise(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                tokens.push(Token { text: &line[s..i], column: s + 1 });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+// Turns the tokens of a line into integers, checking that there's exactly the
+// expected number of them
+fn parse_values(tokens: &[Token], expected: usize, line_number: usize,
+                line: &str) -> Result<Vec<i32>, ParseError> {
+    if let Some(extra) = tokens.get(expected) {
+        return Err(ParseError::new(line_number, extra.column,
+            format!("Unexpected extra value '{}'", extra.text)));
+    }
+    if tokens.len() < expected {
+        return Err(ParseError::new(line_number, line.trim_end().len() + 1,
+            format!("Expected {} values but found {}", expected, tokens.len())));
+    }
+
+    tokens.iter().map(|t| {
+        t.text.parse().map_err(|_| ParseError::new(line_number, t.column,
+            format!("Failed to parse '{}' as an integer", t.text)))
+    }).collect()
+}
+
+fn parse_command(tokens: &[Token], line_number: usize, line: &str, register_count: usize)
+    -> Result<Command, ParseError> {
+    let name = &tokens[0];
+    let opcode = Opcode::from_name(name.text).ok_or_else(|| {
+        ParseError::new(line_number, name.column,
+                        format!("Unknown opcode '{}'", name.text))
+    })?;
+
+    let values = parse_values(&tokens[1..], 3, line_number, line)?;
+    let command = Command { opcode, a: values[0], b: values[1], c: values[2] };
+    if let Some(operand) = missing_register(&command, register_count) {
+        return Err(ParseError::new(line_number, tokens[operand + 1].column,
+            format!("There's no register {}, registers go from 0 to {}",
+                    values[operand], register_count - 1)));
+    }
+    Ok(command)
+}
+
+// The first operand of the command, counting a, b and c from 0, which names a
+// register the processor doesn't have
+fn missing_register(command: &Command, register_count: usize) -> Option<usize> {
+    let (a_is_register, b_is_register) = command.opcode.register_operands();
+    let operands = [(command.a, a_is_register), (command.b, b_is_register), (command.c, true)];
+    operands.iter().position(|&(value, is_register)| {
+        is_register && (value < 0 || value as usize >= register_count)
+    })
+}
+
+impl Program {
+    // The device in days 19 and 21 has six registers
+    pub const REGISTER_COUNT: usize = 6;
+
+    pub fn parse(input: &str) -> Result<Program, ParseError> {
+        Program::parse_with_registers(input, Program::REGISTER_COUNT)
+    }
+
+    pub fn parse_with_registers(input: &str, register_count: usize)
+        -> Result<Program, ParseError> {
+        let mut ip_register = None;
+        let mut commands = vec![];
+        for (i, line) in input.lines().enumerate() {
+            let line_number = i + 1;
+            let tokens = tokenise(line);
+            if tokens.is_empty() {
+                continue;
+            }
+
+            if tokens[0].text == "#ip" {
+                if ip_register.is_some() {
+                    return Err(ParseError::new(line_number, tokens[0].column,
+                        "Duplicate instruction pointer declaration".to_string()));
+                }
+                let value = parse_values(&tokens[1..], 1, line_number, line)?[0];
+                if value < 0 || value as usize >= register_count {
+                    return Err(ParseError::new(line_number, tokens[1].column,
+                        format!("Invalid instruction pointer register {}, registers go \
+                                 from 0 to {}", value, register_count - 1)));
+                }
+                ip_register = Some(value as usize);
+            }
+            else {
+                commands.push(parse_command(&tokens, line_number, line, register_count)?);
+            }
+        }
+
+        match ip_register {
+            Some(ip_register) => Ok(Program { ip_register, register_count, commands }),
+            None => Err(ParseError::new(1, 1,
+                "Didn't find the instruction pointer register".to_string())),
+        }
+    }
+}
+
+impl std::str::FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Program, Self::Err> {
+        Program::parse(s)
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {} {}", self.opcode, self.a, self.b, self.c)
+    }
+}
+
+// Prints the program in exactly the form it's parsed from, so that parsing
+// the printed text gives back an identical program
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "#ip {}", self.ip_register)?;
+        for command in &self.commands {
+            writeln!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+// The numeric form of a command used by day 16, before we know which number
+// corresponds to which opcode
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RawCommand {
+    pub opcode: i32,
+    pub a: i32,
+    pub b: i32,
+    pub c: i32,
+}
+
+impl RawCommand {
+    pub fn parse(line: &str, line_number: usize) -> Result<RawCommand, ParseError> {
+        let values = parse_values(&tokenise(line), 4, line_number, line)?;
+        Ok(RawCommand { opcode: values[0], a: values[1], b: values[2], c: values[3] })
+    }
+}
+
+// Which opcode each of the numbers used in a day 16 program refers to
+pub type OpcodeMap = std::collections::HashMap<i32, Opcode>;
+
+// The first command in a numeric program which can't be disassembled
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DisassembleError {
+    // The opcode number isn't in the mapping
+    UnmappedOpcode { index: usize, opcode: i32 },
+    // An operand names a register the processor doesn't have
+    MissingRegister { index: usize, register: i32, register_count: usize },
+}
+
+impl std::fmt::Display for DisassembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            DisassembleError::UnmappedOpcode { index, opcode } =>
+                write!(f, "Command {} has unmapped opcode {}", index, opcode),
+            DisassembleError::MissingRegister { index, register, register_count } =>
+                write!(f, "Command {} uses register {}, registers go from 0 to {}",
+                       index, register, register_count - 1),
+        }
+    }
+}
+
+impl std::error::Error for DisassembleError {}
+
+// Converts a numeric program into mnemonic commands using a known mapping,
+// checking they only use registers a processor with register_count has
+pub fn disassemble(raw: &[RawCommand], mapping: &OpcodeMap, register_count: usize)
+    -> Result<Vec<Command>, DisassembleError> {
+    raw.iter().enumerate().map(|(index, r)| {
+        let opcode = *mapping.get(&r.opcode)
+            .ok_or(DisassembleError::UnmappedOpcode { index, opcode: r.opcode })?;
+        let command = Command { opcode, a: r.a, b: r.b, c: r.c };
+        match missing_register(&command, register_count) {
+            Some(operand) => Err(DisassembleError::MissingRegister {
+                index, register: [r.a, r.b, r.c][operand], register_count
+            }),
+            None => Ok(command),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example program from day 19
+    const EXAMPLE: &str = "#ip 0
+seti 5 0 1
+seti 6 0 2
+addi 0 1 0
+addr 1 2 3
+setr 1 0 0
+seti 8 0 4
+seti 9 0 5
+";
+
+    #[test]
+    fn printed_program_parses_back_the_same() {
+        let program = Program::parse(EXAMPLE).unwrap();
+        assert_eq!(program.commands.len(), 7);
+        assert_eq!(Program::parse(&program.to_string()).unwrap(), program);
+
+        // Formatting the parser doesn't care about shouldn't matter either
+        let untidy = EXAMPLE.trim_end().replace('\n', "\r\n");
+        assert_eq!(Program::parse(&untidy).unwrap(), program);
+    }
+
+    #[test]
+    fn registers_must_exist() {
+        let error = Program::parse("#ip 0\naddr 9 2 3\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 6));
+        let error = Program::parse("#ip 0\nseti 9 2 -1\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 10));
+        let error = Program::parse("#ip 7\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 5));
+
+        // Immediate values can be anything
+        assert!(Program::parse("#ip 0\naddi 1 99 3\n").is_ok());
+        assert!(Program::parse_with_registers("#ip 7\naddr 9 2 3\n", 10).is_ok());
+    }
+
+    #[test]
+    fn disassembled_registers_must_exist() {
+        let mapping: OpcodeMap = vec![(9, Opcode::Addr), (4, Opcode::Seti)].into_iter().collect();
+        let raw = |line| RawCommand::parse(line, 1).unwrap();
+
+        assert_eq!(disassemble(&[raw("4 7 0 1"), raw("9 3 0 1")], &mapping, 4),
+                   Ok(vec![Command { opcode: Opcode::Seti, a: 7, b: 0, c: 1 },
+                           Command { opcode: Opcode::Addr, a: 3, b: 0, c: 1 }]));
+        assert_eq!(disassemble(&[raw("4 0 0 1"), raw("9 7 0 1")], &mapping, 4),
+                   Err(DisassembleError::MissingRegister {
+                       index: 1, register: 7, register_count: 4 }));
+        assert_eq!(disassemble(&[raw("5 0 0 1")], &mapping, 4),
+                   Err(DisassembleError::UnmappedOpcode { index: 0, opcode: 5 }));
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+
+use crate::opcode::Opcode::*;
+use crate::program::Program;
+
+// A register's value in terms of the values the registers held beforehand.
+// Constants are folded with wrapping i64 arithmetic, so the expressions only
+// describe programs whose values fit in that.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Const(i64),
+    // The value register n held on entry
+    Input(usize),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    // 1 if the first value is greater, otherwise 0
+    Gt(Box<Expr>, Box<Expr>),
+    // 1 if the values are equal, otherwise 0
+    Eq(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn add(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) => Expr::Const(x.wrapping_add(y)),
+            (Expr::Const(0), e) | (e, Expr::Const(0)) => e,
+            // Keep constants on the right and gather them together
+            (Expr::Const(x), e) => Expr::add(e, Expr::Const(x)),
+            (Expr::Add(e, c), Expr::Const(y)) => match *c {
+                Expr::Const(x) => Expr::add(*e, Expr::Const(x.wrapping_add(y))),
+                c => Expr::Add(Box::new(Expr::Add(e, Box::new(c))), Box::new(Expr::Const(y))),
+            },
+            (a, b) => Expr::Add(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn mul(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) => Expr::Const(x.wrapping_mul(y)),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (Expr::Const(1), e) | (e, Expr::Const(1)) => e,
+            (Expr::Const(x), e) => Expr::mul(e, Expr::Const(x)),
+            (Expr::Mul(e, c), Expr::Const(y)) => match *c {
+                Expr::Const(x) => Expr::mul(*e, Expr::Const(x.wrapping_mul(y))),
+                c => Expr::Mul(Box::new(Expr::Mul(e, Box::new(c))), Box::new(Expr::Const(y))),
+            },
+            (a, b) => Expr::Mul(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn and(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) => Expr::Const(x & y),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (a, b) if a == b => a,
+            (a, b) => Expr::And(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn or(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) => Expr::Const(x | y),
+            (Expr::Const(0), e) | (e, Expr::Const(0)) => e,
+            (a, b) if a == b => a,
+            (a, b) => Expr::Or(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn gt(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) => Expr::Const((x > y) as i64),
+            (a, b) if a == b => Expr::Const(0),
+            (a, b) => Expr::Gt(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn eq(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(x), Expr::Const(y)) => Expr::Const((x == y) as i64),
+            (a, b) if a == b => Expr::Const(1),
+            (a, b) => Expr::Eq(Box::new(a), Box::new(b)),
+        }
+    }
+
+    // How tightly the expression binds when written out, higher binds tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Or(..) => 1,
+            Expr::And(..) => 2,
+            Expr::Add(..) => 3,
+            Expr::Mul(..) => 4,
+            _ => 5,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter, parent: u8) -> fmt::Result {
+        if self.precedence() < parent {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+
+    fn fmt_binary(&self, f: &mut fmt::Formatter, a: &Expr, symbol: &str, b: &Expr)
+        -> fmt::Result {
+        let precedence = self.precedence();
+        a.fmt_operand(f, precedence)?;
+        write!(f, "{}", symbol)?;
+        // All the operators are associative, but show any grouping on the
+        // right anyway since it's how the program actually works it out
+        b.fmt_operand(f, precedence + 1)
+    }
+
+    // Comparisons are always bracketed, as are any bitwise operations in them
+    fn fmt_comparison(&self, f: &mut fmt::Formatter, a: &Expr, symbol: &str, b: &Expr)
+        -> fmt::Result {
+        write!(f, "(")?;
+        a.fmt_operand(f, 3)?;
+        write!(f, "{}", symbol)?;
+        b.fmt_operand(f, 3)?;
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Input(register) => write!(f, "r{}", register),
+            Expr::Add(a, b) => self.fmt_binary(f, a, " + ", b),
+            Expr::Mul(a, b) => self.fmt_binary(f, a, "*", b),
+            Expr::And(a, b) => self.fmt_binary(f, a, " & ", b),
+            Expr::Or(a, b) => self.fmt_binary(f, a, " | ", b),
+            Expr::Gt(a, b) => self.fmt_comparison(f, a, " > ", b),
+            Expr::Eq(a, b) => self.fmt_comparison(f, a, " == ", b),
+        }
+    }
+}
+
+// The net effect of running a straight run of commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Effect {
+    pub start: usize,
+    pub end: usize,
+    // The value of every register afterwards, apart from the instruction
+    // pointer which is replaced by next_ip
+    pub registers: Vec<Expr>,
+    // Where execution carries on from
+    pub next_ip: Expr,
+}
+
+impl Effect {
+    // Symbolically execute commands start..=end. Gives None if any command
+    // but the last writes the instruction pointer, since then the commands
+    // don't always all run.
+    pub fn of_range(program: &Program, start: usize, end: usize) -> Option<Effect> {
+        let ip_register = program.ip_register;
+        let register_count = program.commands[start..=end].iter()
+            .flat_map(|c| {
+                let (a_register, b_register) = c.opcode.register_operands();
+                let a = if a_register { c.a } else { 0 };
+                let b = if b_register { c.b } else { 0 };
+                vec![a, b, c.c]
+            })
+            .map(|r| r as usize + 1)
+            .chain(Some(ip_register + 1))
+            .max()
+            .unwrap_or(0);
+        let mut registers: Vec<_> = (0..register_count).map(Expr::Input).collect();
+
+        for i in start..=end {
+            let command = &program.commands[i];
+            if i != end && command.c as usize == ip_register {
+                return None;
+            }
+            registers[ip_register] = Expr::Const(i as i64);
+
+            let (a_register, b_register) = command.opcode.register_operands();
+            let operand = |value: i32, is_register: bool| {
+                if is_register { registers[value as usize].clone() } else { Expr::Const(value as i64) }
+            };
+            let a = operand(command.a, a_register);
+            let b = operand(command.b, b_register);
+            registers[command.c as usize] = match command.opcode {
+                Addr | Addi => Expr::add(a, b),
+                Mulr | Muli => Expr::mul(a, b),
+                Banr | Bani => Expr::and(a, b),
+                Borr | Bori => Expr::or(a, b),
+                Setr | Seti => a,
+                Gtir | Gtri | Gtrr => Expr::gt(a, b),
+                Eqir | Eqri | Eqrr => Expr::eq(a, b),
+            };
+        }
+
+        let next_ip = Expr::add(registers[ip_register].clone(), Expr::Const(1));
+        registers[ip_register] = Expr::Input(ip_register);
+        Some(Effect { start, end, registers, next_ip })
+    }
+
+    // The registers which end up with something other than what they started
+    // with
+    pub fn changes(&self) -> impl Iterator<Item = (usize, &Expr)> {
+        self.registers.iter().enumerate().filter(|&(r, e)| *e != Expr::Input(r))
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (register, expr) in self.changes() {
+            writeln!(f, "r{} = {}", register, expr)?;
+        }
+        writeln!(f, "ip = {}", self.next_ip)
+    }
+}
@@ -0,0 +1,70 @@
+use std::convert::TryFrom;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::ops::{BitAnd, BitOr};
+
+// What to do when addition or multiplication doesn't fit in a register
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Arithmetic {
+    // Stop and report the overflow
+    Checked,
+    // Wrap around, as if only the low bits of the result were kept
+    #[default]
+    Wrapping,
+    // Clamp to the largest or smallest value a register can hold
+    Saturating,
+}
+
+// A type which can be used for the values in the processor's registers
+pub trait Word: Copy + Eq + Ord + Hash + Default + Debug + Display +
+                BitAnd<Output = Self> + BitOr<Output = Self> {
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn from_i64(value: i64) -> Option<Self>;
+    fn to_i64(self) -> Option<i64>;
+
+    fn add(self, other: Self, arithmetic: Arithmetic) -> Option<Self>;
+    fn mul(self, other: Self, arithmetic: Arithmetic) -> Option<Self>;
+
+    fn from_bool(value: bool) -> Self {
+        if value { Self::ONE } else { Self::ZERO }
+    }
+}
+
+macro_rules! word {
+    ($t:ty) => {
+        impl Word for $t {
+            const ZERO: $t = 0;
+            const ONE: $t = 1;
+
+            fn from_i64(value: i64) -> Option<$t> {
+                <$t>::try_from(value).ok()
+            }
+
+            fn to_i64(self) -> Option<i64> {
+                i64::try_from(self).ok()
+            }
+
+            fn add(self, other: $t, arithmetic: Arithmetic) -> Option<$t> {
+                match arithmetic {
+                    Arithmetic::Checked => self.checked_add(other),
+                    Arithmetic::Wrapping => Some(self.wrapping_add(other)),
+                    Arithmetic::Saturating => Some(self.saturating_add(other)),
+                }
+            }
+
+            fn mul(self, other: $t, arithmetic: Arithmetic) -> Option<$t> {
+                match arithmetic {
+                    Arithmetic::Checked => self.checked_mul(other),
+                    Arithmetic::Wrapping => Some(self.wrapping_mul(other)),
+                    Arithmetic::Saturating => Some(self.saturating_mul(other)),
+                }
+            }
+        }
+    }
+}
+
+word!(i32);
+word!(i64);
+word!(u64);
@@ -0,0 +1,114 @@
+// Loading the character grids used as puzzle input by days 13 and 15 of
+// Advent of Code 2018, collecting everything wrong with a grid rather than
+// stopping at the first problem
+
+// Something wrong with a grid at a particular place. Lines and columns count
+// from 1, like a text editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Problem {
+    // A problem with the square at (x, y) in the grid
+    pub fn at(x: usize, y: usize, message: String) -> Problem {
+        Problem { line: y + 1, column: x + 1, message }
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for Problem {}
+
+// Every problem found with a grid, in the order they appear in the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problems(pub Vec<Problem>);
+
+impl std::fmt::Display for Problems {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Found {} problem(s) with the grid:", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Problems {}
+
+// A rectangular grid of characters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    pub width: usize,
+    pub height: usize,
+    cells: Vec<char>,
+}
+
+impl Grid {
+    pub fn get(&self, x: usize, y: usize) -> char {
+        self.cells[x + (self.width * y)]
+    }
+
+    // Every square as (x, y, c), in reading order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, char)> + '_ {
+        let width = self.width;
+        self.cells.iter().enumerate().map(move |(i, &c)| (i % width, i / width, c))
+    }
+
+    // Check the grid is surrounded by walls, with a problem for every square
+    // around the edge which isn't one
+    pub fn check_border(&self, wall: char) -> Vec<Problem> {
+        self.iter()
+            .filter(|&(x, y, c)| {
+                let edge = x == 0 || y == 0 || x == self.width - 1 || y == self.height - 1;
+                edge && c != wall
+            })
+            .map(|(x, y, c)| Problem::at(x, y, format!(
+                "Expected '{}' around the edge of the grid but found '{}'", wall, c)))
+            .collect()
+    }
+}
+
+// Read a grid with a line of input per row. Every line has to be as long as
+// the first and every character has to be one that `is_known` accepts. Blank
+// lines at the end of the input are ignored.
+pub fn load<F>(input: &str, is_known: F) -> Result<Grid, Problems> where F: Fn(char) -> bool {
+    let lines: Vec<_> = input.trim_end_matches(['\n', '\r']).lines().collect();
+    let width = lines.first().map_or(0, |l| l.chars().count());
+    if width == 0 {
+        return Err(Problems(vec![Problem { line: 1, column: 1,
+                                           message: "The grid is empty".to_string() }]));
+    }
+
+    let mut problems = vec![];
+    let mut cells = Vec::with_capacity(width * lines.len());
+    for (y, line) in lines.iter().enumerate() {
+        let length = line.chars().count();
+        if length != width {
+            problems.push(Problem::at(length.min(width), y, format!(
+                "Expected {} characters on the line but found {}", width, length)));
+        }
+        for (x, c) in line.chars().enumerate() {
+            if !is_known(c) {
+                problems.push(Problem::at(x, y, format!("Unknown character '{}'", c)));
+            }
+            if x < width {
+                cells.push(c);
+            }
+        }
+    }
+
+    problems.sort_by_key(|p| (p.line, p.column));
+    if problems.is_empty() {
+        Ok(Grid { width, height: lines.len(), cells })
+    }
+    else {
+        Err(Problems(problems))
+    }
+}